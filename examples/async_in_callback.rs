@@ -1,4 +1,11 @@
-use std::{error::Error, time::Duration};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use moleculer::{
     config::{ConfigBuilder, Transporter},
@@ -19,13 +26,28 @@ async fn main() -> eyre::Result<()> {
         .add_callback(print_async)
         .build();
 
+    // async closures can capture state
+    let counter = Arc::new(AtomicUsize::new(0));
+    let count_async = EventBuilder::new("countAsync")
+        .add_callback(move |_ctx: Context<Event>| {
+            let counter = Arc::clone(&counter);
+            async move {
+                hello_from_async().await;
+                let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                println!("countAsync received {} times", count);
+                Ok::<(), eyre::Report>(())
+            }
+        })
+        .build();
+
     let print_normal = EventBuilder::new("printNormal")
         .add_callback(print_normal)
         .build();
 
     let greeter_service = Service::new("asyncGreeter")
         .add_event(print_normal)
-        .add_event(print_async);
+        .add_event(print_async)
+        .add_event(count_async);
 
     let service_broker = ServiceBroker::new(config).add_service(greeter_service);
     service_broker.start().await;
@@ -33,9 +55,9 @@ async fn main() -> eyre::Result<()> {
     Ok(())
 }
 
-fn print_async(_ctx: Context<Event>) -> Result<(), Box<dyn Error>> {
+async fn print_async(_ctx: Context<Event>) -> eyre::Result<()> {
     println!("Starting");
-    tokio::spawn(async { hello_from_async().await });
+    hello_from_async().await;
     println!("Ended");
     Ok(())
 }
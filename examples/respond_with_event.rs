@@ -34,16 +34,15 @@ async fn main() -> eyre::Result<()> {
 
 fn emit_hi(ctx: Context<Event>) -> Result<(), Box<dyn Error>> {
     println!("Received emitHi in rust");
-    ctx.broker.emit("test", serde_json::json!({}));
+    ctx.emit("test", serde_json::json!({}));
 
     Ok(())
 }
 
 fn broadcast_name(ctx: Context<Event>) -> Result<(), Box<dyn Error>> {
-    let msg: PrintNameMessage = serde_json::from_value(ctx.params)?;
+    let msg: PrintNameMessage = serde_json::from_value(ctx.params.clone())?;
     println!("Received broadcastName in rust");
-    ctx.broker
-        .broadcast("testWithParam", serde_json::to_value(&msg)?);
+    ctx.broadcast("testWithParam", serde_json::to_value(&msg)?);
 
     Ok(())
 }
@@ -24,15 +24,13 @@ async fn main() -> eyre::Result<()> {
 
     Ok(())
 }
-fn math_add(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+fn math_add(ctx: ActionContext) -> Result<i32, Box<dyn Error>> {
     // get message decode using serde
-    let msg: ActionMessage = serde_json::from_value(ctx.params.clone())?;
+    let msg: ActionMessage = serde_json::from_value(ctx.params)?;
     let answer = msg.a + msg.b;
 
-    // serialize reply using serde and send
-    ctx.reply(answer.into());
-
-    Ok(())
+    // returned value is serialized using serde and sent as the reply
+    Ok(answer)
 }
 
 #[derive(Deserialize)]
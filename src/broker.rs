@@ -1,12 +1,16 @@
 mod registry;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc},
+    time::Instant,
+};
 
 use act_zero::*;
 use async_trait::async_trait;
-use log::{error, warn};
-use serde_json::Value;
-use tokio::sync::oneshot::Sender;
+use log::warn;
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
 
 use crate::{
     channels::messages::{
@@ -14,6 +18,7 @@ use crate::{
             DisconnectMessage, EventMessage, HeartbeatMessage, InfoMessage, RequestMessage,
         },
         outgoing::{self},
+        MoleculerError,
     },
     service::Action,
 };
@@ -21,7 +26,9 @@ use crate::{
 use crate::{
     channels::{self, ChannelSupervisor},
     config::{self, Channel, DeserializeError, Serializer},
-    service::{Context, Event, Service},
+    service::{CallOptions, Callback, CallbackError, Context, Event, ParentContext, Service},
+    transporter::Ack,
+    EmitSender, MaxCallLevelError, RequestTimeoutError, ResponseSender,
 };
 
 use thiserror::Error;
@@ -51,11 +58,8 @@ pub(crate) enum Error {
     #[error("Unable to find callback function for action '{0}'")]
     ActionCallbackNotFound(String),
 
-    #[error("Call back function failed to complete: {0}")]
-    ActionCallbackFailed(String),
-
-    #[error("Node not found for ('{0}') event or action")]
-    NodeNotFound(String),
+    #[error("Call back function for action '{0}' failed to complete: {1}")]
+    ActionCallbackFailed(String, String),
 }
 
 #[allow(dead_code)]
@@ -71,6 +75,13 @@ pub(crate) struct ServiceBroker {
 
     pub(crate) registry: Registry,
 
+    /// `disable_balancer` is set and the transporter balances requests and events
+    balanced: bool,
+    /// the transporter keeps the events until a node of their group reads them
+    stores_events: bool,
+    /// the balanced listeners of the services added before starting are started
+    listening: bool,
+
     pid: Addr<Self>,
     channel_supervisor: Addr<ChannelSupervisor>,
     config: Arc<config::Config>,
@@ -94,7 +105,14 @@ impl From<&Vec<Service>> for Events {
         Events(
             services
                 .iter()
-                .flat_map(|service| service.events.clone())
+                .flat_map(|service| {
+                    service.events.iter().map(move |(name, event)| {
+                        let mut event = event.clone();
+                        event.service = Some(service.full_name());
+
+                        (name.clone(), event)
+                    })
+                })
                 .collect(),
         )
     }
@@ -115,7 +133,14 @@ impl From<&Vec<Service>> for Actions {
         Actions(
             services
                 .iter()
-                .flat_map(|service| service.actions.clone())
+                .flat_map(|service| {
+                    service.actions.iter().map(move |(name, action)| {
+                        let mut action = action.clone();
+                        action.service = Some(service.full_name());
+
+                        (name.clone(), action)
+                    })
+                })
                 .collect(),
         )
     }
@@ -130,10 +155,17 @@ impl Actor for ServiceBroker {
             .await
             .map_err(Error::Channel)?;
 
+        self.channel_supervisor = channel_supervisor.clone();
+
+        // after the services added before starting
+        send!(self.pid.start_balanced_listeners());
+
         send!(self.pid.broadcast_info());
         send!(channel_supervisor.broadcast_discover());
-
-        self.channel_supervisor = channel_supervisor.clone();
+        send!(self.pid.emit_local(
+            "$transporter.connected".to_string(),
+            json!({ "wasReconnect": false })
+        ));
 
         self.pid
             .send_fut(async move { channels::listen_for_disconnect(channel_supervisor).await });
@@ -143,8 +175,9 @@ impl Actor for ServiceBroker {
 
     async fn error(&mut self, error: ActorError) -> bool {
         log::error!("ServiceBroker Actor Error: {:?}", error);
-        // do not stop on actor error
-        false
+
+        // stop if the transporter could not be started, do not stop on any other actor error
+        matches!(error.downcast_ref::<Error>(), Some(Error::Channel(_)))
     }
 }
 impl ServiceBroker {
@@ -161,6 +194,10 @@ impl ServiceBroker {
             events: Events::new(),
             actions: Actions::new(),
 
+            balanced: false,
+            stores_events: false,
+            listening: false,
+
             pid: Addr::detached(),
             channel_supervisor: Addr::detached(),
             config: Arc::new(config),
@@ -168,65 +205,219 @@ impl ServiceBroker {
     }
 
     // exposed publicly via crate::ServiceBroker
-    pub(crate) async fn emit(&mut self, event_name: String, params: Value) -> ActorResult<()> {
-        let node_name = self
-            .registry
-            .get_node_name_for_event(&event_name)
-            .ok_or_else(|| Error::NodeNotFound(event_name.clone()))?;
+    pub(crate) async fn emit(
+        &mut self,
+        event_name: String,
+        params: Value,
+        parent: Option<ParentContext>,
+        tx: EmitSender,
+    ) -> ActorResult<()> {
+        if self.balanced || self.stores_events {
+            return self.emit_balanced(event_name, params, parent, tx).await;
+        }
+
+        let node_name = match self.registry.get_node_name_for_event(&event_name) {
+            Some(node_name) => node_name,
+            None => {
+                let _ = tx.send(Err(crate::Error::EventHandlerNotFound(event_name)));
+                return Produces::ok(());
+            }
+        };
 
         let node_event_channel = Channel::Event.external_channel(&self.config, node_name);
 
-        let message = outgoing::EventMessage::new_for_emit(&self.config, &event_name, params);
+        let message = outgoing::EventMessage::new_for_emit(
+            &self.config,
+            &event_name,
+            params,
+            parent.as_ref(),
+        );
 
-        send!(self
-            .channel_supervisor
-            .publish_to_channel(node_event_channel, serde_json::to_vec(&message)?));
+        let packets = vec![(node_event_channel, self.serializer.serialize(&message)?)];
+        self.publish_with_result(packets, tx);
 
         Produces::ok(())
     }
 
-    pub(crate) async fn broadcast(&self, event_name: String, params: Value) -> ActorResult<()> {
-        let node_names = self
-            .registry
-            .get_all_nodes_for_event(&event_name)
-            .ok_or_else(|| Error::NodeNotFound(event_name.clone()))?;
+    /// Send the event once to each group listening to it, the transporter picks the node of the group.
+    ///
+    /// When the transporter stores the events, they are also sent to the groups whose nodes are all gone,
+    /// and read once a node of the group is back.
+    async fn emit_balanced(
+        &self,
+        event_name: String,
+        params: Value,
+        parent: Option<ParentContext>,
+        tx: EmitSender,
+    ) -> ActorResult<()> {
+        let groups = if self.stores_events {
+            self.registry.get_known_groups_for_event(&event_name)
+        } else {
+            self.registry.get_groups_for_event(&event_name)
+        };
+
+        let groups = match groups {
+            Some(groups) => groups,
+            None => {
+                let _ = tx.send(Err(crate::Error::EventHandlerNotFound(event_name)));
+                return Produces::ok(());
+            }
+        };
+
+        let mut packets = Vec::with_capacity(groups.len());
+
+        for group in groups {
+            let mut message = outgoing::EventMessage::new_for_emit(
+                &self.config,
+                &event_name,
+                params.clone(),
+                parent.as_ref(),
+            );
+            message.groups = Some(vec![group.clone()]);
+
+            let group_event_channel = Channel::EventBalanced
+                .external_channel(&self.config, format!("{}.{}", group, event_name));
+
+            packets.push((group_event_channel, self.serializer.serialize(&message)?));
+        }
 
-        let message = outgoing::EventMessage::new_for_broadcast(&self.config, &event_name, params);
+        self.publish_with_result(packets, tx);
+        Produces::ok(())
+    }
 
-        for node_name in node_names {
-            let node_event_channel = Channel::Event.external_channel(&self.config, node_name);
+    pub(crate) async fn broadcast(
+        &self,
+        event_name: String,
+        params: Value,
+        parent: Option<ParentContext>,
+        tx: EmitSender,
+    ) -> ActorResult<()> {
+        let node_names = match self.registry.get_all_nodes_for_event(&event_name) {
+            Some(node_names) => node_names,
+            None => {
+                let _ = tx.send(Err(crate::Error::EventHandlerNotFound(event_name)));
+                return Produces::ok(());
+            }
+        };
+
+        let message = outgoing::EventMessage::new_for_broadcast(
+            &self.config,
+            &event_name,
+            params,
+            parent.as_ref(),
+        );
 
-            send!(self
-                .channel_supervisor
-                .publish_to_channel(node_event_channel, serde_json::to_vec(&message)?));
-        }
+        let message = self.serializer.serialize(&message)?;
+        let packets = node_names
+            .into_iter()
+            .map(|node_name| {
+                let node_event_channel = Channel::Event.external_channel(&self.config, node_name);
+                (node_event_channel, message.clone())
+            })
+            .collect();
 
+        self.publish_with_result(packets, tx);
         Produces::ok(())
     }
 
+    /// Publish the packets in the background, the first publish error is sent to the emitter
+    fn publish_with_result(&self, packets: Vec<(String, Vec<u8>)>, tx: EmitSender) {
+        let channel_supervisor = self.channel_supervisor.clone();
+
+        self.pid.send_fut(async move {
+            let mut result = Ok(());
+
+            for (channel, message) in packets {
+                match call!(channel_supervisor.publish_to_channel(channel, message)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(error)) => {
+                        result = Err(error.into());
+                        break;
+                    }
+                    Err(_) => {
+                        result = Err(crate::Error::UnknownError);
+                        break;
+                    }
+                }
+            }
+
+            let _ = tx.send(result);
+        });
+    }
+
     pub(crate) async fn call(
         &mut self,
         action: String,
         params: Value,
-        tx: Sender<Value>,
+        options: CallOptions,
+        tx: ResponseSender,
     ) -> ActorResult<()> {
-        let node_name = self
-            .registry
-            .get_node_name_for_action(&action)
-            .ok_or_else(|| Error::NodeNotFound(action.clone()))?;
+        // the transporter picks the node unless the call targets one
+        let node_name = match &options.node_id {
+            Some(node_id) if self.registry.node_has_action(node_id, &action) => {
+                Some(node_id.clone())
+            }
+            Some(node_id) => {
+                let error = crate::Error::ServiceNotAvailable {
+                    action,
+                    node_id: node_id.clone(),
+                };
+
+                let _ = tx.send(Err(error));
+                return Produces::ok(());
+            }
+            None => match self.registry.get_node_name_for_action(&action) {
+                Some(_) if self.balanced => None,
+                Some(node_name) => Some(node_name),
+                None => {
+                    let _ = tx.send(Err(crate::Error::ServiceNotFound(action)));
+                    return Produces::ok(());
+                }
+            },
+        };
+
+        // explicit timeout, then whatever is left of the parent's timeout, then the default
+        let timeout = options
+            .timeout
+            .or_else(|| {
+                let deadline = options.parent_ctx.as_ref()?.deadline?;
+                Some(deadline.saturating_duration_since(Instant::now()))
+            })
+            .unwrap_or(self.config.request_timeout);
+
+        let node_request_channel = match &node_name {
+            Some(node_name) => Channel::Request.external_channel(&self.config, node_name),
+            None => Channel::RequestBalanced.external_channel(&self.config, &action),
+        };
+        let message =
+            outgoing::RequestMessage::new(&self.config, &action, params, timeout, &options);
+
+        if self.exceeds_max_call_level(message.level) {
+            let error = MaxCallLevelError {
+                node_id: self.node_id.clone(),
+                level: message.level,
+            };
+
+            let _ = tx.send(Err(error.into()));
+            return Produces::ok(());
+        }
 
-        let node_request_channel = Channel::Request.external_channel(&self.config, &node_name);
-        let message = outgoing::RequestMessage::new(&self.config, &action, params);
-        let serialized_message = serde_json::to_vec(&message)?;
+        let serialized_message = self.serializer.serialize(&message)?;
 
-        call!(self
-            .channel_supervisor
-            .start_response_waiter(node_name, message.request_id, tx))
+        call!(self.channel_supervisor.start_response_waiter(
+            timeout,
+            action.clone(),
+            node_name,
+            message.id.clone(),
+            tx
+        ))
         .await?;
 
-        send!(self
-            .channel_supervisor
-            .publish_to_channel(node_request_channel, serialized_message));
+        send!(self.channel_supervisor.publish_request(
+            node_request_channel,
+            serialized_message,
+            message.id.clone()
+        ));
 
         Produces::ok(())
     }
@@ -238,7 +429,24 @@ impl ServiceBroker {
 
         send!(self
             .channel_supervisor
-            .publish_to_channel(reply_channel, serde_json::to_vec(&message)?));
+            .publish_to_channel(reply_channel, self.serializer.serialize(&message)?));
+
+        Produces::ok(())
+    }
+
+    pub(crate) async fn reply_error(
+        &self,
+        node: String,
+        id: String,
+        error: MoleculerError,
+    ) -> ActorResult<()> {
+        let message = outgoing::ResponseMessage::new_error(&self.config, &id, error);
+
+        let reply_channel = Channel::Response.external_channel(&self.config, node);
+
+        send!(self
+            .channel_supervisor
+            .publish_to_channel(reply_channel, self.serializer.serialize(&message)?));
 
         Produces::ok(())
     }
@@ -272,16 +480,25 @@ impl ServiceBroker {
         }
     }
 
-    pub(crate) async fn add_service(&mut self, service: Service) {
-        self.services.push(service);
-        self.events = (&self.services).into();
-        self.actions = (&self.services).into();
+    pub(crate) async fn add_service(&mut self, service: Service) -> ActorResult<()> {
+        self.add_services(vec![service]).await
     }
 
-    pub(crate) async fn add_services(&mut self, services: Vec<Service>) {
-        for service in services {
-            self.add_service(service).await;
+    pub(crate) async fn add_services(&mut self, services: Vec<Service>) -> ActorResult<()> {
+        // the services added before starting are listened to once started
+        if self.listening {
+            self.listen_balanced(&services).await?;
         }
+
+        self.services.extend(services);
+        self.events = (&self.services).into();
+        self.actions = (&self.services).into();
+
+        // services can be added after the INFO sent on start, let the other nodes know about them.
+        // Does nothing before the broker has started
+        send!(self.pid.broadcast_info());
+
+        Produces::ok(())
     }
 
     pub(crate) async fn publish_info_to_channel(&self, channel: String) -> ActorResult<()> {
@@ -293,10 +510,52 @@ impl ServiceBroker {
         Produces::ok(())
     }
 
+    /// Hand an event to the local services only, ex: `$transporter.connected`
+    pub(crate) async fn emit_local(&self, event_name: String, params: Value) -> ActorResult<()> {
+        if self.events.get(&event_name).is_none() {
+            return Produces::ok(());
+        }
+
+        let event_message = EventMessage::new_local(&self.config, &event_name, params);
+        self.handle_incoming_event(Ok(event_message), Ack::none())
+            .await
+    }
+
     pub(crate) async fn handle_incoming_event(
         &self,
         event_message: Result<EventMessage, DeserializeError>,
+        ack: Ack,
     ) -> ActorResult<()> {
+        let callback_task = match self.spawn_event_callback(event_message) {
+            Ok(callback_task) => callback_task,
+            Err(err) => {
+                // delivering it again would not help
+                tokio::spawn(ack.ack());
+                return Err(err);
+            }
+        };
+
+        // report failures back to the broker
+        self.pid.send_fut_with(|pid| async move {
+            let result = match callback_task.await {
+                Ok(result) => result
+                    .map(|_| ())
+                    .map_err(|err| Error::EventCallbackFailed(err.to_string())),
+                Err(err) => Err(Error::EventCallbackFailed(err.to_string())),
+            };
+
+            // only once handled, so the event is delivered again if the node stops before
+            ack.ack().await;
+            send!(pid.callback_completed(result));
+        });
+
+        Produces::ok(())
+    }
+
+    fn spawn_event_callback(
+        &self,
+        event_message: Result<EventMessage, DeserializeError>,
+    ) -> Result<JoinHandle<Result<Value, CallbackError>>, ActorError> {
         let event_message = event_message?;
 
         let event = self
@@ -306,19 +565,109 @@ impl ServiceBroker {
 
         let callback = event
             .callback
+            .clone()
             .ok_or_else(|| Error::EventCallbackNotFound(event_message.event.clone()))?;
 
-        let event_context = Context::<Event>::new(event_message, self.pid.clone().into());
-
-        callback(event_context).map_err(|err| Error::EventCallbackFailed(err.to_string()))?;
+        let event_context = Context::<Event>::new(
+            event_message,
+            event.service.clone(),
+            self.pid.clone().into(),
+        );
 
-        Produces::ok(())
+        // run the callback on its own task, a panic or a busy callback can't stop the broker
+        Ok(tokio::spawn(callback.call(event_context)))
     }
 
     pub(crate) async fn handle_incoming_request(
         &self,
         request_message: Result<RequestMessage, DeserializeError>,
+        ack: Ack,
     ) -> ActorResult<()> {
+        let (request_message, request, callback) = match self.find_action(request_message) {
+            Ok(found) => found,
+            Err(err) => {
+                // delivering it again would not help
+                tokio::spawn(ack.ack());
+                return Err(err);
+            }
+        };
+
+        let action = request_message.action.clone();
+        let reply_node = request_message.sender.clone();
+        let reply_id = request_message.id.clone();
+        let node_id = self.node_id.clone();
+
+        if self.exceeds_max_call_level(request_message.level) {
+            let error = MaxCallLevelError {
+                node_id,
+                level: request_message.level,
+            };
+
+            send!(self.pid.reply_error(reply_node, reply_id, error.into()));
+            tokio::spawn(ack.ack());
+            return Produces::ok(());
+        }
+
+        let request_context = Context::<Action>::new(
+            request_message,
+            request.service.clone(),
+            self.pid.clone().into(),
+        );
+        let replied = Arc::clone(&request_context.replied);
+        let deadline = request_context.deadline;
+        let callback_future = callback.call(request_context);
+        let started = Instant::now();
+        let timeout_action = action.clone();
+
+        // run the callback on its own task, a panic or a busy callback can't stop the broker
+        let callback_task = tokio::spawn(async move {
+            // give up early if the caller is not going to wait for the response
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), callback_future)
+                    .await
+                    .unwrap_or_else(|_| {
+                        let error = RequestTimeoutError {
+                            action: timeout_action,
+                            node_id,
+                            elapsed: started.elapsed(),
+                        };
+
+                        Err(MoleculerError::from(error).into())
+                    }),
+                None => callback_future.await,
+            }
+        });
+
+        // send the returned value as the reply
+        self.pid.send_fut_with(|pid| async move {
+            let result = callback_task.await.unwrap_or_else(|err| {
+                Err(Error::ActionCallbackFailed(action.clone(), err.to_string()).into())
+            });
+
+            // unless the callback already replied with `Context::reply()`
+            if !replied.swap(true, Ordering::SeqCst) {
+                match result {
+                    Ok(value) => send!(pid.reply(reply_node, reply_id, value)),
+                    Err(err) => {
+                        warn!("Call back function for action '{}' failed: {}", action, err);
+
+                        let error = MoleculerError::from_callback_error(err);
+                        send!(pid.reply_error(reply_node, reply_id, error))
+                    }
+                }
+            }
+
+            // only once handled, so the request is delivered again if the node stops before
+            ack.ack().await;
+        });
+
+        Produces::ok(())
+    }
+
+    fn find_action(
+        &self,
+        request_message: Result<RequestMessage, DeserializeError>,
+    ) -> Result<(RequestMessage, &Action, Callback<Action>), ActorError> {
         let request_message = request_message?;
 
         let request = self
@@ -328,16 +677,70 @@ impl ServiceBroker {
 
         let callback = request
             .callback
+            .clone()
             .ok_or_else(|| Error::ActionCallbackNotFound(request_message.action.clone()))?;
 
-        let request_context = Context::<Action>::new(request_message, self.pid.clone().into());
+        Ok((request_message, request, callback))
+    }
 
-        callback(request_context).map_err(|err| Error::ActionCallbackFailed(err.to_string()))?;
+    fn exceeds_max_call_level(&self, level: i32) -> bool {
+        // 0 disables the limit
+        self.config.max_call_level > 0 && level > self.config.max_call_level as i32
+    }
 
+    async fn callback_completed(&self, result: Result<(), Error>) -> ActorResult<()> {
+        result?;
         Produces::ok(())
     }
 
-    async fn broadcast_info(&self) -> ActorResult<()> {
+    async fn start_balanced_listeners(&mut self) -> ActorResult<()> {
+        self.stores_events = call!(self.channel_supervisor.stores_events()).await?;
+
+        if self.config.disable_balancer {
+            self.balanced = call!(self.channel_supervisor.has_built_in_balancer()).await?;
+
+            if !self.balanced {
+                warn!("The transporter has no built-in balancer, ignoring disable_balancer");
+            }
+        }
+
+        self.listening = true;
+        self.listen_balanced(&self.services).await?;
+
+        Produces::ok(())
+    }
+
+    /// Start the balanced listeners of the actions and events of the services
+    async fn listen_balanced(&self, services: &[Service]) -> Result<(), ActorError> {
+        let actions = if self.balanced {
+            services
+                .iter()
+                .flat_map(|service| service.actions.keys().cloned())
+                .collect()
+        } else {
+            vec![]
+        };
+
+        // events stored by the transporter are always read through the group
+        let events = if self.balanced || self.stores_events {
+            services
+                .iter()
+                .flat_map(|service| service.event_groups())
+                .map(|(event, group)| (event.clone(), group.to_string()))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        call!(self
+            .channel_supervisor
+            .start_balanced_listeners(actions, events))
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn broadcast_info(&self) -> ActorResult<()> {
         self.publish_info_to_channel(Channel::Info.channel_to_string(&self.config))
             .await
     }
@@ -20,12 +20,15 @@ use super::ServiceBroker;
 
 pub(crate) type ActionName = String;
 pub(crate) type EventName = String;
+pub(crate) type GroupName = String;
 pub(crate) type NodeName = String;
 
 pub(crate) struct Registry {
     actions: HashMap<EventName, QueueSet<NodeName>>,
     events: HashMap<EventName, QueueSet<NodeName>>,
     nodes: HashMap<NodeName, Node>,
+    /// `(event, group)` of every node seen, kept once the nodes are gone
+    known_event_groups: HashSet<(EventName, GroupName)>,
 }
 
 impl Registry {
@@ -34,6 +37,7 @@ impl Registry {
             actions: HashMap::new(),
             events: HashMap::new(),
             nodes: HashMap::new(),
+            known_event_groups: HashSet::new(),
         }
     }
 
@@ -43,6 +47,34 @@ impl Registry {
         Some(event_nodes.iter().cloned().collect())
     }
 
+    /// Groups listening to the event, the event is balanced between the nodes of each group
+    pub(crate) fn get_groups_for_event(&self, event_name: &str) -> Option<HashSet<GroupName>> {
+        let groups: HashSet<GroupName> = self
+            .nodes
+            .values()
+            .flat_map(|node| node.event_groups.iter())
+            .filter(|(event, _)| event == event_name)
+            .map(|(_, group)| group.clone())
+            .collect();
+
+        (!groups.is_empty()).then_some(groups)
+    }
+
+    /// Groups that listened to the event on any node seen so far, including the nodes that are gone
+    pub(crate) fn get_known_groups_for_event(
+        &self,
+        event_name: &str,
+    ) -> Option<HashSet<GroupName>> {
+        let groups: HashSet<GroupName> = self
+            .known_event_groups
+            .iter()
+            .filter(|(event, _)| event == event_name)
+            .map(|(_, group)| group.clone())
+            .collect();
+
+        (!groups.is_empty()).then_some(groups)
+    }
+
     pub(crate) fn get_node_name_for_event(&mut self, event_name: &str) -> Option<NodeName> {
         let event_nodes = self.events.get_mut(event_name)?;
         event_nodes.get_round_robin()
@@ -53,6 +85,12 @@ impl Registry {
         action_nodes.get_round_robin()
     }
 
+    pub(crate) fn node_has_action(&self, node_name: &str, action_name: &str) -> bool {
+        self.nodes
+            .get(node_name)
+            .is_some_and(|node| node.actions.contains(action_name))
+    }
+
     pub(crate) fn add_or_update_node(
         &mut self,
         broker: Addr<ServiceBroker>,
@@ -95,6 +133,17 @@ impl Registry {
             node.events.insert(event_name.clone());
         }
 
+        let event_groups = info
+            .services
+            .iter()
+            .flat_map(|service| service.event_groups());
+        for (event_name, group) in event_groups {
+            node.event_groups
+                .insert((event_name.clone(), group.to_string()));
+            self.known_event_groups
+                .insert((event_name.clone(), group.to_string()));
+        }
+
         // get action_names from info message
         let action_names = info
             .services
@@ -177,6 +226,7 @@ pub(crate) struct Node {
     pub(crate) client: Client,
     pub(crate) instance_id: String,
     pub(crate) events: HashSet<EventName>,
+    pub(crate) event_groups: HashSet<(EventName, GroupName)>,
     pub(crate) actions: HashSet<ActionName>,
 }
 
@@ -194,6 +244,7 @@ impl Node {
             client: info.client.clone(),
             instance_id: info.instance_id.clone(),
             events: hashset![],
+            event_groups: hashset![],
             actions: hashset![],
         }
     }
@@ -13,32 +13,34 @@ mod response;
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use act_zero::runtimes::tokio::spawn_actor;
 use act_zero::*;
 use async_trait::async_trait;
-use log::{debug, error};
-use serde_json::Value;
+use futures::StreamExt as _;
+use log::{debug, error, info, warn};
+use serde_json::json;
 use thiserror::Error;
-use tokio::sync::oneshot::Sender;
 
 use crate::{
     broker::ServiceBroker,
     config,
-    config::{Channel, Config, Transporter},
-    nats,
+    config::{Channel, Config},
+    transporter::{self, Conn, ConnectionEvent},
+    PublishError, ResponseSender,
 };
 
 use self::{
     disconnect::Disconnect,
     discover::{Discover, DiscoverTargeted},
-    event::Event,
+    event::{Event, EventBalanced},
     heartbeat::Heartbeat,
     info::{Info, InfoTargeted},
     messages::outgoing::DisconnectMessage,
     ping::{Ping, PingTargeted},
     pong::Pong,
-    request::Request,
+    request::{Request, RequestBalanced},
     response::Response,
 };
 
@@ -48,7 +50,7 @@ pub(crate) enum Error {
     UnableToStartListeners,
 
     #[error(transparent)]
-    Nats(#[from] nats::Error),
+    Transporter(#[from] transporter::Error),
 
     #[error(transparent)]
     Deserialize(#[from] config::DeserializeError),
@@ -74,7 +76,7 @@ impl Actor for ChannelSupervisor {
 pub(crate) struct ChannelSupervisor {
     broker: Addr<ServiceBroker>,
 
-    conn: nats::Conn,
+    conn: Conn,
     config: Arc<Config>,
     pid: WeakAddr<Self>,
     channels: HashMap<Channel, String>,
@@ -99,19 +101,18 @@ pub(crate) struct ChannelSupervisor {
 
     pong: Addr<Pong>,
     disconnect: Addr<Disconnect>,
+
+    // balanced channels, one per local action and event
+    request_balanced: Vec<Addr<RequestBalanced>>,
+    event_balanced: Vec<Addr<EventBalanced>>,
 }
 
 impl ChannelSupervisor {
-    async fn new(broker: Addr<ServiceBroker>, config: Arc<Config>) -> Self {
+    async fn new(broker: Addr<ServiceBroker>, config: Arc<Config>) -> Result<Self, Error> {
         let channels = Channel::build_hashmap(&config);
+        let conn = transporter::connect(&config).await?;
 
-        let conn = match &config.transporter {
-            Transporter::Nats(nats_address) => nats::Conn::new(nats_address)
-                .await
-                .expect("NATS should connect"),
-        };
-
-        Self {
+        Ok(Self {
             broker,
             conn,
             config,
@@ -137,7 +138,10 @@ impl ChannelSupervisor {
 
             pong: Addr::detached(),
             disconnect: Addr::detached(),
-        }
+
+            request_balanced: vec![],
+            event_balanced: vec![],
+        })
     }
 
     async fn start_listeners(&mut self) -> ActorResult<()> {
@@ -187,6 +191,71 @@ impl ChannelSupervisor {
 
         self.response = spawn_actor(Response::new(&self.config, &self.conn).await);
 
+        if let Some(events) = self.conn.connection_events() {
+            let pid = self.pid.clone();
+
+            self.pid.send_fut(events.for_each(move |event| {
+                send!(pid.connection_changed(event));
+                async {}
+            }));
+        }
+
+        Produces::ok(())
+    }
+
+    /// Peers may have dropped the node after missing its heartbeats, announce it again once reconnected
+    async fn connection_changed(&self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::Reconnected => {
+                info!("Transporter reconnected, sending INFO and DISCOVER");
+
+                send!(self.broker.broadcast_info());
+                send!(self.discover.broadcast());
+                send!(self.broker.emit_local(
+                    "$transporter.connected".to_string(),
+                    json!({ "wasReconnect": true })
+                ));
+            }
+            ConnectionEvent::Disconnected => {
+                warn!("Transporter disconnected");
+
+                send!(self.broker.emit_local(
+                    "$transporter.disconnected".to_string(),
+                    json!({ "graceFul": false })
+                ));
+            }
+        }
+    }
+
+    pub(crate) async fn has_built_in_balancer(&self) -> ActorResult<bool> {
+        Produces::ok(self.conn.has_built_in_balancer())
+    }
+
+    pub(crate) async fn stores_events(&self) -> ActorResult<bool> {
+        Produces::ok(self.conn.stores_events())
+    }
+
+    /// Listen for the balanced requests of the actions and the balanced events of the `(event, group)`
+    pub(crate) async fn start_balanced_listeners(
+        &mut self,
+        actions: Vec<String>,
+        events: Vec<(String, String)>,
+    ) -> ActorResult<()> {
+        let broker_pid = self.broker.clone().downgrade();
+
+        for action in actions {
+            self.request_balanced.push(spawn_actor(
+                RequestBalanced::new(broker_pid.clone(), &self.config, &self.conn, action).await,
+            ));
+        }
+
+        for (event, group) in events {
+            self.event_balanced.push(spawn_actor(
+                EventBalanced::new(broker_pid.clone(), &self.config, &self.conn, event, group)
+                    .await,
+            ));
+        }
+
         Produces::ok(())
     }
 
@@ -198,31 +267,49 @@ impl ChannelSupervisor {
         &self,
         channel: T,
         message: Vec<u8>,
-    ) -> ActorResult<()>
+    ) -> ActorResult<Result<(), PublishError>>
     where
         T: AsRef<str>,
     {
-        let res = self.conn.send(channel.as_ref(), message).await;
+        Produces::ok(self.try_publish(channel.as_ref(), message).await)
+    }
 
-        if let Err(err) = res {
-            error!("Unable to send message: {}", err)
+    /// Publish a request, the caller gets the error if it could not be sent
+    pub(crate) async fn publish_request(
+        &self,
+        channel: String,
+        message: Vec<u8>,
+        request_id: String,
+    ) -> ActorResult<()> {
+        if let Err(error) = self.try_publish(&channel, message).await {
+            send!(self.response.request_failed(request_id, error));
         }
 
         Produces::ok(())
     }
 
+    async fn try_publish(&self, channel: &str, message: Vec<u8>) -> Result<(), PublishError> {
+        self.conn.publish(channel, message).await.map_err(|err| {
+            error!("Unable to send message: {}", err);
+
+            PublishError {
+                topic: channel.to_string(),
+                reason: err.to_string(),
+            }
+        })
+    }
+
     pub(crate) async fn start_response_waiter(
         &self,
-        node_name: String,
+        timeout: Duration,
+        action: String,
+        node_name: Option<String>,
         request_id: String,
-        tx: Sender<Value>,
+        tx: ResponseSender,
     ) -> ActorResult<()> {
-        call!(self.response.start_response_waiter(
-            self.config.request_timeout,
-            node_name,
-            request_id,
-            tx
-        ))
+        call!(self
+            .response
+            .start_response_waiter(timeout, action, node_name, request_id, tx))
         .await?;
 
         Produces::ok(())
@@ -234,7 +321,7 @@ impl ChannelSupervisor {
             .get(&channel)
             .expect("should always find channel");
 
-        let _ = self.publish_to_channel(channel.as_str(), message).await;
+        let _ = self.try_publish(channel, message).await;
 
         debug!("Message published to channel: {}", channel);
 
@@ -249,6 +336,8 @@ impl ChannelSupervisor {
             .await;
 
         debug!("Disconnect message sent");
+
+        self.conn.disconnect().await?;
         Produces::ok(())
     }
 }
@@ -257,7 +346,7 @@ pub(crate) async fn start_supervisor(
     broker: Addr<ServiceBroker>,
     config: Arc<Config>,
 ) -> Result<Addr<ChannelSupervisor>, Error> {
-    let channel_supervisor = spawn_actor(ChannelSupervisor::new(broker, config).await);
+    let channel_supervisor = spawn_actor(ChannelSupervisor::new(broker, config).await?);
 
     call!(channel_supervisor.start_listeners())
         .await
@@ -1,13 +1,13 @@
 use crate::{
     broker::ServiceBroker,
     config::{Channel, Config},
-    nats::Conn,
+    transporter::Conn,
 };
 
 use super::messages::incoming::DisconnectMessage;
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::StreamExt as _;
 use log::{debug, error, info};
 use std::sync::Arc;
@@ -46,14 +46,13 @@ impl Disconnect {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for DISCONNECT messages");
 
         let mut channel = self
             .conn
             .subscribe(&Channel::Disconnect.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -62,11 +61,13 @@ impl Disconnect {
                     Err(e) => error!("Unable to handle DISCONNECT message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let disconnect_msg: DisconnectMessage = self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let disconnect_msg: DisconnectMessage = self.config.serializer.deserialize(&msg)?;
 
         send!(self.broker.handle_disconnect_message(disconnect_msg));
 
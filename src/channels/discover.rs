@@ -1,13 +1,13 @@
 use crate::{
     broker::ServiceBroker,
     config::{Channel, Config},
-    nats::Conn,
+    transporter::Conn,
 };
 
 use super::{messages::incoming, messages::outgoing, ChannelSupervisor};
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::StreamExt as _;
 use log::{debug, error, info};
 use std::sync::Arc;
@@ -49,13 +49,12 @@ impl Discover {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for DISCOVER messages");
         let mut channel = self
             .conn
             .subscribe(&Channel::Discover.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -64,7 +63,9 @@ impl Discover {
                     Err(e) => error!("Unable to handle DISCOVER message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
     pub(crate) async fn broadcast(&self) {
@@ -78,9 +79,8 @@ impl Discover {
         ));
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let discover: incoming::DiscoverMessage =
-            self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let discover: incoming::DiscoverMessage = self.config.serializer.deserialize(&msg)?;
 
         let channel = format!(
             "{}.{}",
@@ -130,13 +130,12 @@ impl DiscoverTargeted {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for DISCOVER (targeted) messages");
         let mut channel = self
             .conn
             .subscribe(&Channel::DiscoverTargeted.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -145,12 +144,13 @@ impl DiscoverTargeted {
                     Err(e) => error!("Unable to handle DISCOVER (targeted): {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let discover: incoming::DiscoverMessage =
-            self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let discover: incoming::DiscoverMessage = self.config.serializer.deserialize(&msg)?;
         let channel = format!(
             "{}.{}",
             Channel::Info.channel_to_string(&self.config),
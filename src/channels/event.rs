@@ -2,12 +2,12 @@ use crate::{
     broker::ServiceBroker,
     channels::messages::incoming::EventMessage,
     config::{self, Channel, Config},
-    nats::Conn,
+    transporter::{Ack, Conn},
 };
 
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use config::DeserializeError;
 use futures::StreamExt;
 use log::{debug, error, info};
@@ -47,29 +47,108 @@ impl Event {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for EVENT messages");
         let mut channel = self
             .conn
-            .subscribe(&Channel::Event.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .subscribe_acked(&Channel::Event.channel_to_string(&self.config))
+            .await?;
 
         pid.clone().send_fut(async move {
-            while let Some(msg) = channel.next().await {
-                match call!(pid.handle_message(msg)).await {
+            while let Some((msg, ack)) = channel.next().await {
+                match call!(pid.handle_message(msg, ack)).await {
                     Ok(_) => debug!("Successfully handled EVENT message"),
                     Err(e) => error!("Unable to handle EVENT message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
+    }
+
+    async fn handle_message(&self, msg: Bytes, ack: Ack) -> ActorResult<()> {
+        let event_context: Result<EventMessage, DeserializeError> =
+            self.config.serializer.deserialize(&msg);
+
+        send!(self.broker.handle_incoming_event(event_context, ack));
+
+        Produces::ok(())
+    }
+}
+
+#[async_trait]
+impl Actor for EventBalanced {
+    async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        let pid_clone = pid.clone();
+        send!(pid_clone.listen(pid));
+        Produces::ok(())
+    }
+
+    async fn error(&mut self, error: ActorError) -> bool {
+        error!("EventBalanced Actor Error: {:?}", error);
+
+        // do not stop on actor error
+        false
+    }
+}
+
+/// Events of a group balanced by the transporter, only used when `disable_balancer` is set
+pub(crate) struct EventBalanced {
+    config: Arc<Config>,
+    broker: WeakAddr<ServiceBroker>,
+    conn: Conn,
+    event: String,
+    group: String,
+}
+
+impl EventBalanced {
+    pub(crate) async fn new(
+        broker: WeakAddr<ServiceBroker>,
+        config: &Arc<Config>,
+        conn: &Conn,
+        event: String,
+        group: String,
+    ) -> Self {
+        Self {
+            broker,
+            conn: conn.clone(),
+            config: Arc::clone(config),
+            event,
+            group,
+        }
+    }
+
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        info!(
+            "Listening for EVENT (balanced) messages of {} in group {}",
+            self.event, self.group
+        );
+
+        let channel_name = Channel::EventBalanced
+            .external_channel(&self.config, format!("{}.{}", self.group, self.event));
+
+        let mut channel = self
+            .conn
+            .subscribe_balanced(&channel_name, &self.group)
+            .await?;
+
+        pid.clone().send_fut(async move {
+            while let Some((msg, ack)) = channel.next().await {
+                match call!(pid.handle_message(msg, ack)).await {
+                    Ok(_) => debug!("Successfully handled EVENT (balanced) message"),
+                    Err(e) => error!("Unable to handle EVENT (balanced) message: {}", e),
+                }
+            }
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
+    async fn handle_message(&self, msg: Bytes, ack: Ack) -> ActorResult<()> {
         let event_context: Result<EventMessage, DeserializeError> =
-            self.config.serializer.deserialize(&msg.payload);
+            self.config.serializer.deserialize(&msg);
 
-        send!(self.broker.handle_incoming_event(event_context));
+        send!(self.broker.handle_incoming_event(event_context, ack));
 
         Produces::ok(())
     }
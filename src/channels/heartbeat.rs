@@ -1,7 +1,7 @@
 use crate::{
     broker::ServiceBroker,
     config::{Channel, Config},
-    nats::Conn,
+    transporter::Conn,
 };
 
 use super::messages::{incoming, outgoing};
@@ -9,8 +9,8 @@ use super::ChannelSupervisor;
 use act_zero::runtimes::tokio::Timer;
 use act_zero::timer::Tick;
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::StreamExt as _;
 use log::{debug, error, info};
 use std::{sync::Arc, time::Duration};
@@ -90,14 +90,13 @@ impl Heartbeat {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for HEARTBEAT messages");
 
         let mut channel = self
             .conn
             .subscribe(&Channel::Heartbeat.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -106,12 +105,13 @@ impl Heartbeat {
                     Err(e) => error!("Unable to handle HEARTBEAT message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let heartbeat: incoming::HeartbeatMessage =
-            self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let heartbeat: incoming::HeartbeatMessage = self.config.serializer.deserialize(&msg)?;
 
         send!(self.broker.handle_heartbeat_message(heartbeat));
 
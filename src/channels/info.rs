@@ -1,13 +1,13 @@
 use crate::{
     broker::ServiceBroker,
     config::{Channel, Config},
-    nats::Conn,
+    transporter::Conn,
 };
 
 use super::messages::incoming::InfoMessage;
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::StreamExt as _;
 use log::{debug, error, info};
 use std::sync::Arc;
@@ -47,13 +47,12 @@ impl Info {
     }
 
     // INFO packets received when a new client connects and broadcasts it's INFO
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for INFO messages");
         let mut channel = self
             .conn
             .subscribe(&Channel::Info.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -62,11 +61,13 @@ impl Info {
                     Err(e) => error!("Unable to handle INFO message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let info_message: InfoMessage = self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let info_message: InfoMessage = self.config.serializer.deserialize(&msg)?;
         send!(self.broker.handle_info_message(info_message));
 
         Produces::ok(())
@@ -107,14 +108,13 @@ impl InfoTargeted {
         }
     }
     // INFO packets received are responses to DISCOVER packet sent by current client
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for INFO (targeted) messages");
 
         let mut channel = self
             .conn
             .subscribe(&Channel::InfoTargeted.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -126,11 +126,13 @@ impl InfoTargeted {
                     ),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let info_message: InfoMessage = self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let info_message: InfoMessage = self.config.serializer.deserialize(&msg)?;
         send!(self.broker.handle_info_message(info_message));
 
         Produces::ok(())
@@ -3,9 +3,13 @@ pub(crate) mod incoming {
     use std::collections::HashMap;
 
     use serde::Deserialize;
-    use serde_json::Value;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
 
-    use crate::service::Service;
+    use crate::{
+        config::{Config, Packet, PacketKind},
+        service::Service,
+    };
 
     #[derive(Deserialize, Debug, Clone)]
     #[serde(rename_all = "camelCase")]
@@ -51,7 +55,9 @@ pub(crate) mod incoming {
         #[serde(rename = "instanceID")]
         pub(crate) instance_id: String,
 
+        #[serde(default)]
         pub(crate) config: HashMap<String, String>,
+        #[serde(default)]
         pub(crate) metadata: HashMap<String, String>,
     }
 
@@ -101,6 +107,29 @@ pub(crate) mod incoming {
         pub(crate) broadcast: Option<bool>,
     }
 
+    impl EventMessage {
+        /// Event raised by the node itself, only handled by its own services
+        pub(crate) fn new_local(config: &Config, event: &str, data: Value) -> Self {
+            Self {
+                id: Uuid::new_v4().to_string(),
+                sender: config.node_id.clone(),
+                ver: "4".to_string(),
+                event: event.to_string(),
+                data,
+                meta: json!({}),
+                level: 1,
+                tracing: None,
+                parent_id: None,
+                request_id: None,
+                caller: None,
+                stream: None,
+                seq: None,
+                groups: None,
+                broadcast: Some(true),
+            }
+        }
+    }
+
     #[derive(Deserialize, Debug)]
     pub(crate) struct RequestMessage {
         pub(crate) id: String,
@@ -149,18 +178,57 @@ pub(crate) mod incoming {
         pub(crate) meta: Value,
 
         #[serde(default)]
-        pub(crate) error: Option<crate::channels::messages::MoleculerError>,
+        pub(crate) error: Option<super::MoleculerError>,
 
         #[serde(default)]
         pub(crate) success: bool,
     }
+    impl Packet for PingMessage {
+        const KIND: PacketKind = PacketKind::Ping;
+    }
+
+    impl Packet for HeartbeatMessage {
+        const KIND: PacketKind = PacketKind::Heartbeat;
+    }
+
+    impl Packet for DisconnectMessage {
+        const KIND: PacketKind = PacketKind::Disconnect;
+    }
+
+    impl Packet for InfoMessage {
+        const KIND: PacketKind = PacketKind::Info;
+    }
+
+    impl Packet for DiscoverMessage {
+        const KIND: PacketKind = PacketKind::Discover;
+    }
+
+    impl Packet for EventMessage {
+        const KIND: PacketKind = PacketKind::Event;
+    }
+
+    impl Packet for RequestMessage {
+        const KIND: PacketKind = PacketKind::Request;
+    }
+
+    impl Packet for ResponseMessage {
+        const KIND: PacketKind = PacketKind::Response;
+    }
 }
 
 pub(crate) mod outgoing {
-    use std::{collections::HashMap, time::SystemTime};
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
 
     use super::incoming::PingMessage;
-    use crate::{built_info, config::Config, service::Service};
+    use crate::{
+        built_info,
+        config::{Config, Packet, PacketKind},
+        service::{CallOptions, ParentContext, Service},
+        util,
+    };
     use serde::Serialize;
     use serde_json::{json, Value};
     use uuid::Uuid;
@@ -305,13 +373,13 @@ pub(crate) mod outgoing {
         pub(crate) tracing: Option<bool>,
 
         #[serde(rename = "parentID", default)]
-        pub(crate) parent_id: &'a Option<String>,
+        pub(crate) parent_id: Option<&'a str>,
 
         #[serde(rename = "requestID", default)]
-        pub(crate) request_id: &'a Option<String>,
+        pub(crate) request_id: Option<&'a str>,
 
         #[serde(rename = "caller", default)]
-        pub(crate) caller: &'a Option<String>,
+        pub(crate) caller: Option<&'a str>,
 
         #[serde(default)]
         pub(crate) stream: Option<bool>,
@@ -327,7 +395,12 @@ pub(crate) mod outgoing {
     }
 
     impl<'a> EventMessage<'a> {
-        pub(crate) fn new_for_emit(config: &'a Config, event: &'a str, params: Value) -> Self {
+        pub(crate) fn new_for_emit(
+            config: &'a Config,
+            event: &'a str,
+            params: Value,
+            parent: Option<&'a ParentContext>,
+        ) -> Self {
             Self {
                 event,
 
@@ -335,14 +408,14 @@ pub(crate) mod outgoing {
                 id: Uuid::new_v4().to_string(),
                 sender: &config.node_id,
                 data: params,
-                meta: json!({}),
-                level: 1,
+                meta: parent.map_or_else(|| json!({}), |parent| parent.meta.clone()),
+                level: parent.map_or(1, |parent| parent.level + 1),
 
                 tracing: None,
-                parent_id: &None,
-                request_id: &None,
+                parent_id: parent.map(|parent| parent.id.as_str()),
+                request_id: parent.map(|parent| parent.request_id.as_str()),
 
-                caller: &None,
+                caller: parent.and_then(|parent| parent.caller.as_deref()),
                 stream: None,
                 seq: None,
                 groups: None,
@@ -350,10 +423,15 @@ pub(crate) mod outgoing {
             }
         }
 
-        pub(crate) fn new_for_broadcast(config: &'a Config, event: &'a str, params: Value) -> Self {
+        pub(crate) fn new_for_broadcast(
+            config: &'a Config,
+            event: &'a str,
+            params: Value,
+            parent: Option<&'a ParentContext>,
+        ) -> Self {
             Self {
                 broadcast: Some(true),
-                ..EventMessage::new_for_emit(config, event, params)
+                ..EventMessage::new_for_emit(config, event, params, parent)
             }
         }
     }
@@ -371,7 +449,7 @@ pub(crate) mod outgoing {
         pub(crate) meta: Value,
 
         #[serde(default)]
-        pub(crate) error: Option<crate::channels::messages::MoleculerError>,
+        pub(crate) error: Option<super::MoleculerError>,
 
         #[serde(default)]
         pub(crate) success: bool,
@@ -389,6 +467,18 @@ pub(crate) mod outgoing {
                 error: None,
             }
         }
+
+        pub(crate) fn new_error(
+            config: &'a Config,
+            request_id: &'a str,
+            error: super::MoleculerError,
+        ) -> Self {
+            Self {
+                success: false,
+                error: Some(error.set_node_id(&config.node_id)),
+                ..ResponseMessage::new(config, request_id, Value::Null)
+            }
+        }
     }
 
     #[derive(Serialize, Debug)]
@@ -428,40 +518,180 @@ pub(crate) mod outgoing {
     }
 
     impl<'a> RequestMessage<'a> {
-        pub(crate) fn new(config: &'a Config, action_name: &'a str, params: Value) -> Self {
-            let id = Uuid::new_v4();
+        pub(crate) fn new(
+            config: &'a Config,
+            action_name: &'a str,
+            params: Value,
+            timeout: Duration,
+            options: &'a CallOptions,
+        ) -> Self {
+            let id = Uuid::new_v4().to_string();
+            let parent = options.parent_ctx.as_ref();
+
+            let meta = parent.map_or_else(|| json!({}), |parent| parent.meta.clone());
 
             Self {
                 ver: "4",
                 sender: &config.node_id,
-                id: id.to_string(),
+                id: id.clone(),
 
                 params,
                 action: action_name,
 
-                meta: serde_json::Value::default(),
+                meta: util::merge_meta(meta, options.meta.as_ref()),
 
-                timeout: config.request_timeout as f32,
-                level: 1,
+                timeout: timeout.as_millis() as f32,
+                level: parent.map_or(1, |parent| parent.level + 1),
 
                 tracing: None,
-                parent_id: None,
+                parent_id: parent.map(|parent| parent.id.as_str()),
 
-                request_id: id.to_string(),
-                caller: None,
+                request_id: parent.map_or(id, |parent| parent.request_id.clone()),
+                caller: parent.and_then(|parent| parent.caller.as_deref()),
 
                 stream: None,
                 seq: None,
             }
         }
     }
+    impl Packet for PongMessage<'_> {
+        const KIND: PacketKind = PacketKind::Pong;
+    }
+
+    impl Packet for HeartbeatMessage<'_> {
+        const KIND: PacketKind = PacketKind::Heartbeat;
+    }
+
+    impl Packet for DisconnectMessage<'_> {
+        const KIND: PacketKind = PacketKind::Disconnect;
+    }
+
+    impl Packet for DiscoverMessage<'_> {
+        const KIND: PacketKind = PacketKind::Discover;
+    }
+
+    impl Packet for InfoMessage<'_> {
+        const KIND: PacketKind = PacketKind::Info;
+    }
+
+    impl Packet for EventMessage<'_> {
+        const KIND: PacketKind = PacketKind::Event;
+    }
+
+    impl Packet for ResponseMessage<'_> {
+        const KIND: PacketKind = PacketKind::Response;
+    }
+
+    impl Packet for RequestMessage<'_> {
+        const KIND: PacketKind = PacketKind::Request;
+    }
+}
+
+/// Error sent between nodes when an action fails, has the same shape as the errors from
+/// [Moleculer JS](https://moleculer.services/docs/0.14/errors.html).
+///
+/// Return it from an action callback to control the error the caller receives.
+/// ```rust
+/// use moleculer::{MoleculerError, ActionContext};
+///
+/// fn find_user(ctx: ActionContext) -> Result<serde_json::Value, MoleculerError> {
+///     Err(MoleculerError::new("User not found")
+///         .set_code(404)
+///         .set_type("USER_NOT_FOUND")
+///         .set_data(ctx.params))
+/// }
+/// ```
+#[derive(serde::Serialize, serde::Deserialize, thiserror::Error, Debug, Clone, PartialEq)]
+#[error("{name}: {message}")]
+pub struct MoleculerError {
+    #[serde(default = "default_error_name")]
+    pub name: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(
+        default = "default_error_code",
+        deserialize_with = "deserialize_error_code"
+    )]
+    pub code: i32,
+    #[serde(rename = "type", default)]
+    pub type_: Option<String>,
+    #[serde(default)]
+    pub data: serde_json::Value,
+    #[serde(rename = "nodeID", default)]
+    pub node_id: Option<String>,
+    #[serde(default)]
+    pub retryable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<String>,
+}
+
+impl MoleculerError {
+    /// Create a new error with the message, defaults to code `500`.
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        Self {
+            name: default_error_name(),
+            message: message.into(),
+            code: default_error_code(),
+            type_: None,
+            data: serde_json::Value::Null,
+            node_id: None,
+            retryable: false,
+            stack: None,
+        }
+    }
+
+    pub fn set_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn set_code(mut self, code: i32) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn set_type<S: Into<String>>(mut self, type_: S) -> Self {
+        self.type_ = Some(type_.into());
+        self
+    }
+
+    pub fn set_data(mut self, data: serde_json::Value) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub fn set_retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub(crate) fn set_node_id<S: Into<String>>(mut self, node_id: S) -> Self {
+        self.node_id = Some(node_id.into());
+        self
+    }
+
+    /// Keeps errors returned as a [MoleculerError] intact, any other error only keeps its message.
+    pub(crate) fn from_callback_error(error: crate::service::CallbackError) -> Self {
+        match error.downcast::<MoleculerError>() {
+            Ok(error) => *error,
+            Err(error) => MoleculerError::new(error.to_string()),
+        }
+    }
+}
+
+fn default_error_name() -> String {
+    "MoleculerError".to_string()
+}
+
+fn default_error_code() -> i32 {
+    500
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub(crate) struct MoleculerError {
-    message: String,
-    code: i8,
-    #[serde(rename = "type")]
-    type_: String,
-    data: serde_json::Value,
+// moleculer js sends `null` when an error does not have a code
+fn deserialize_error_code<'de, D>(deserializer: D) -> Result<i32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let code: Option<i32> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(code.unwrap_or_else(default_error_code))
 }
@@ -1,6 +1,6 @@
 use crate::{
     config::{Channel, Config},
-    nats::Conn,
+    transporter::Conn,
 };
 
 use super::{
@@ -9,8 +9,8 @@ use super::{
 };
 
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::StreamExt as _;
 use log::{debug, error, info};
 use std::sync::Arc;
@@ -49,14 +49,13 @@ impl Ping {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for PING messages");
 
         let mut channel = self
             .conn
             .subscribe(&Channel::Ping.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -65,11 +64,13 @@ impl Ping {
                     Err(e) => error!("Unable to handle PING message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let ping_message: PingMessage = self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let ping_message: PingMessage = self.config.serializer.deserialize(&msg)?;
         let channel = format!(
             "{}.{}",
             Channel::PongPrefix.channel_to_string(&self.config),
@@ -120,14 +121,13 @@ impl PingTargeted {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for PING (targeted) messages");
 
         let mut channel = self
             .conn
             .subscribe(&Channel::PingTargeted.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -136,11 +136,13 @@ impl PingTargeted {
                     Err(e) => error!("Unable to handle PING message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let ping_message: PingMessage = self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&self, msg: Bytes) -> ActorResult<()> {
+        let ping_message: PingMessage = self.config.serializer.deserialize(&msg)?;
         let channel = format!(
             "{}.{}",
             Channel::PongPrefix.channel_to_string(&self.config),
@@ -1,12 +1,12 @@
 use crate::{
     config::{Channel, Config},
-    nats::Conn,
+    transporter::Conn,
 };
 
-use super::{ChannelSupervisor, Error};
+use super::ChannelSupervisor;
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::StreamExt as _;
 use log::{debug, error, info};
 use std::sync::Arc;
@@ -29,7 +29,7 @@ impl Actor for Pong {
 #[allow(dead_code)]
 pub(crate) struct Pong {
     config: Arc<Config>,
-    channel: async_nats::Subscriber,
+    conn: Conn,
     parent: WeakAddr<ChannelSupervisor>,
 }
 
@@ -50,28 +50,33 @@ impl Pong {
     ) -> Self {
         Self {
             parent,
-            channel: conn
-                .subscribe(&Channel::Pong.channel_to_string(config))
-                .await
-                .unwrap(),
+            conn: conn.clone(),
             config: Arc::clone(config),
         }
     }
 
-    pub(crate) async fn listen(&mut self, _pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for PONG messages");
+        let mut channel = self
+            .conn
+            .subscribe(&Channel::Pong.channel_to_string(&self.config))
+            .await?;
 
-        while let Some(msg) = self.channel.next().await {
-            match self.handle_message(msg).await {
-                Ok(_) => debug!("Successfully handled PONG message"),
-                Err(e) => error!("Unable to handle PONG message: {}", e),
+        pid.clone().send_fut(async move {
+            while let Some(msg) = channel.next().await {
+                match call!(pid.handle_message(msg)).await {
+                    Ok(_) => debug!("Successfully handled PONG message"),
+                    Err(e) => error!("Unable to handle PONG message: {}", e),
+                }
             }
-        }
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, _msg: Message) -> Result<(), Error> {
-        // let pong_msg: PongMessage = self.config.serializer.deserialize(&msg.payload)?;
-        // do nothing with incoming disconnect messages for now
-        Ok(())
+    async fn handle_message(&self, _msg: Bytes) -> ActorResult<()> {
+        // let pong_msg: PongMessage = self.config.serializer.deserialize(&msg)?;
+        // do nothing with incoming pong messages for now
+        Produces::ok(())
     }
 }
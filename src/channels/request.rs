@@ -2,12 +2,12 @@ use crate::{
     broker::ServiceBroker,
     channels::messages::incoming::RequestMessage,
     config::{self, Channel, Config},
-    nats::Conn,
+    transporter::{Ack, Conn},
 };
 
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use config::DeserializeError;
 use futures::StreamExt as _;
 use log::{debug, error, info};
@@ -47,29 +47,103 @@ impl Request {
         }
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for REQUEST messages");
         let mut channel = self
             .conn
-            .subscribe(&Channel::Request.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .subscribe_acked(&Channel::Request.channel_to_string(&self.config))
+            .await?;
 
         pid.clone().send_fut(async move {
-            while let Some(msg) = channel.next().await {
-                match call!(pid.handle_message(msg)).await {
+            while let Some((msg, ack)) = channel.next().await {
+                match call!(pid.handle_message(msg, ack)).await {
                     Ok(_) => debug!("Successfully handled REQUEST message"),
                     Err(e) => error!("Unable to handle REQUEST message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
+    }
+
+    async fn handle_message(&self, msg: Bytes, ack: Ack) -> ActorResult<()> {
+        let request_context: Result<RequestMessage, DeserializeError> =
+            self.config.serializer.deserialize(&msg);
+
+        send!(self.broker.handle_incoming_request(request_context, ack));
+
+        Produces::ok(())
+    }
+}
+
+#[async_trait]
+impl Actor for RequestBalanced {
+    async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        let pid_clone = pid.clone();
+        send!(pid_clone.listen(pid));
+        Produces::ok(())
+    }
+
+    async fn error(&mut self, error: ActorError) -> bool {
+        error!("RequestBalanced Actor Error: {:?}", error);
+
+        // do not stop on actor error
+        false
+    }
+}
+
+/// Requests for an action balanced by the transporter, only used when `disable_balancer` is set
+pub(crate) struct RequestBalanced {
+    config: Arc<Config>,
+    broker: WeakAddr<ServiceBroker>,
+    conn: Conn,
+    action: String,
+}
+
+impl RequestBalanced {
+    pub(crate) async fn new(
+        broker: WeakAddr<ServiceBroker>,
+        config: &Arc<Config>,
+        conn: &Conn,
+        action: String,
+    ) -> Self {
+        Self {
+            broker,
+            conn: conn.clone(),
+            config: Arc::clone(config),
+            action,
+        }
+    }
+
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        info!(
+            "Listening for REQUEST (balanced) messages of {}",
+            self.action
+        );
+        let channel_name = Channel::RequestBalanced.external_channel(&self.config, &self.action);
+
+        let mut channel = self
+            .conn
+            .subscribe_balanced(&channel_name, &self.action)
+            .await?;
+
+        pid.clone().send_fut(async move {
+            while let Some((msg, ack)) = channel.next().await {
+                match call!(pid.handle_message(msg, ack)).await {
+                    Ok(_) => debug!("Successfully handled REQUEST (balanced) message"),
+                    Err(e) => error!("Unable to handle REQUEST (balanced) message: {}", e),
+                }
+            }
+        });
+
+        Produces::ok(())
     }
 
-    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
+    async fn handle_message(&self, msg: Bytes, ack: Ack) -> ActorResult<()> {
         let request_context: Result<RequestMessage, DeserializeError> =
-            self.config.serializer.deserialize(&msg.payload);
+            self.config.serializer.deserialize(&msg);
 
-        send!(self.broker.handle_incoming_request(request_context));
+        send!(self.broker.handle_incoming_request(request_context, ack));
 
         Produces::ok(())
     }
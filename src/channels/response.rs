@@ -1,25 +1,33 @@
 use crate::{
-    channels::messages::incoming::ResponseMessage,
+    channels::messages::{incoming::ResponseMessage, MoleculerError},
     config::{Channel, Config},
-    nats::Conn,
+    transporter::Conn,
+    PublishError, RequestTimeoutError, ResponseSender,
 };
 
 use act_zero::runtimes::tokio::{spawn_actor, Timer};
 use act_zero::timer::Tick;
 use act_zero::*;
-use async_nats::Message;
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::StreamExt as _;
 use log::{debug, error, info};
-use serde_json::Value;
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::sync::oneshot::Sender;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 type RequestId = String;
 
+/// Node named in the timeout of a request balanced by the transporter
+const BALANCED_NODE: &str = "balanced";
+
 #[async_trait]
 impl Actor for Response {
     async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        self.pid = pid.downgrade();
+
         let pid_clone = pid.clone();
         send!(pid_clone.listen(pid));
         Produces::ok(())
@@ -33,6 +41,7 @@ impl Actor for Response {
     }
 }
 pub(crate) struct Response {
+    pid: WeakAddr<Self>,
     config: Arc<Config>,
     waiters: HashMap<RequestId, Addr<ResponseWaiter>>,
     conn: Conn,
@@ -41,6 +50,7 @@ pub(crate) struct Response {
 impl Response {
     pub(crate) async fn new(config: &Arc<Config>, conn: &Conn) -> Self {
         Self {
+            pid: WeakAddr::detached(),
             conn: conn.clone(),
             config: Arc::clone(config),
             waiters: HashMap::new(),
@@ -49,13 +59,16 @@ impl Response {
 
     pub(crate) async fn start_response_waiter(
         &mut self,
-        timeout: i32,
-        node_name: String,
+        timeout: Duration,
+        action: String,
+        node_name: Option<String>,
         request_id: RequestId,
-        tx: Sender<Value>,
+        tx: ResponseSender,
     ) {
         let response_waiter_pid = spawn_actor(ResponseWaiter::new(
+            self.pid.clone(),
             timeout,
+            action,
             request_id.clone(),
             node_name,
             tx,
@@ -64,13 +77,12 @@ impl Response {
         self.waiters.insert(request_id, response_waiter_pid);
     }
 
-    pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
+    pub(crate) async fn listen(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         info!("Listening for RESPONSE messages");
         let mut channel = self
             .conn
             .subscribe(&Channel::Response.channel_to_string(&self.config))
-            .await
-            .unwrap();
+            .await?;
 
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
@@ -79,15 +91,24 @@ impl Response {
                     Err(e) => error!("Unable to handle REQUEST message: {}", e),
                 }
             }
-        })
+        });
+
+        Produces::ok(())
+    }
+
+    /// The request could not be sent, fail the call right away
+    pub(crate) async fn request_failed(&mut self, request_id: RequestId, error: PublishError) {
+        if let Some(response_waiter) = self.waiters.remove(&request_id) {
+            send!(response_waiter.send_error(error.into()));
+        }
     }
 
     async fn timeout_reached(&mut self, request_id: String) {
         self.waiters.remove(&request_id);
     }
 
-    async fn handle_message(&mut self, msg: Message) -> ActorResult<()> {
-        let response: ResponseMessage = self.config.serializer.deserialize(&msg.payload)?;
+    async fn handle_message(&mut self, msg: Bytes) -> ActorResult<()> {
+        let response: ResponseMessage = self.config.serializer.deserialize(&msg)?;
         let response_id = response.id.clone();
 
         if let Some(response_waiter) = self.waiters.get(&response_id) {
@@ -109,7 +130,7 @@ impl Actor for ResponseWaiter {
 
         // Start the timer
         self.timer
-            .set_timeout_for_weak(pid.downgrade(), Duration::from_millis(self.timeout as u64));
+            .set_timeout_for_weak(pid.downgrade(), self.timeout);
 
         Produces::ok(())
     }
@@ -126,6 +147,21 @@ impl Actor for ResponseWaiter {
 impl Tick for ResponseWaiter {
     async fn tick(&mut self) -> ActorResult<()> {
         if self.timer.tick() {
+            if let Some(tx) = self.tx.take() {
+                let error = RequestTimeoutError {
+                    action: self.action.clone(),
+                    // any node could have handled a balanced request
+                    node_id: self
+                        .node_name
+                        .clone()
+                        .unwrap_or_else(|| BALANCED_NODE.to_string()),
+                    elapsed: self.started.elapsed(),
+                };
+
+                // the caller might have stopped waiting for the response
+                let _ = tx.send(Err(error.into()));
+            }
+
             send!(self.parent.timeout_reached(self.request_id.clone()))
         }
         Produces::ok(())
@@ -137,21 +173,33 @@ struct ResponseWaiter {
     pid: WeakAddr<Self>,
     request_id: RequestId,
 
-    timeout: i32,
-    node_name: String,
-    tx: Option<Sender<Value>>,
+    timeout: Duration,
+    started: Instant,
+    action: String,
+    /// `None` for balanced requests, any node with the action can respond
+    node_name: Option<String>,
+    tx: Option<ResponseSender>,
 
     timer: Timer,
 }
 
 impl ResponseWaiter {
-    fn new(timeout: i32, request_id: RequestId, node_name: String, tx: Sender<Value>) -> Self {
+    fn new(
+        parent: WeakAddr<Response>,
+        timeout: Duration,
+        action: String,
+        request_id: RequestId,
+        node_name: Option<String>,
+        tx: ResponseSender,
+    ) -> Self {
         Self {
-            parent: WeakAddr::detached(),
+            parent,
             pid: WeakAddr::detached(),
 
             request_id,
             timeout,
+            started: Instant::now(),
+            action,
             node_name,
             tx: Some(tx),
 
@@ -159,16 +207,63 @@ impl ResponseWaiter {
         }
     }
 
+    async fn send_error(&mut self, error: crate::Error) {
+        // already taken if the timeout was reached
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(Err(error));
+        }
+    }
+
     async fn send_response(&mut self, response: ResponseMessage) -> ActorResult<()> {
-        if self.node_name != response.sender {
+        if self
+            .node_name
+            .as_ref()
+            .is_some_and(|node_name| node_name != &response.sender)
+        {
             // something went wrong here, should handle this error better
             error!("Node name does not match sender")
         }
 
-        // take the tx from actor state and replace it with a none
-        let tx = std::mem::take(&mut self.tx).unwrap();
+        // take the tx from actor state and replace it with a none,
+        // already taken if the timeout was reached
+        let tx = match self.tx.take() {
+            Some(tx) => tx,
+            None => return Produces::ok(()),
+        };
+
+        let result = if response.success {
+            Ok(response.data)
+        } else {
+            let sender = response.sender;
+            let error = response.error.unwrap_or_else(|| {
+                MoleculerError::new("Remote action failed without an error")
+                    .set_node_id(sender.clone())
+            });
+
+            Err(self.remote_error(error, sender))
+        };
 
-        tx.send(response.data).unwrap();
+        // the caller might have stopped waiting for the response
+        let _ = tx.send(result);
         Produces::ok(())
     }
+
+    /// The remote node gives up on its own when the request times out, report it like a local timeout.
+    /// Timeouts of calls made by the remote action are kept as remote errors.
+    fn remote_error(&self, error: MoleculerError, sender: String) -> crate::Error {
+        let timed_out = error.name == "RequestTimeoutError"
+            && error.data.get("action").and_then(|action| action.as_str())
+                == Some(self.action.as_str());
+
+        if !timed_out {
+            return error.into();
+        }
+
+        RequestTimeoutError {
+            action: self.action.clone(),
+            node_id: sender,
+            elapsed: self.started.elapsed(),
+        }
+        .into()
+    }
 }
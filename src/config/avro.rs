@@ -0,0 +1,475 @@
+//! Avro serializer, compatible with the moleculerjs `Avro` serializer.
+//!
+//! Each packet kind is a record with the fields of the avsc schemas of moleculerjs, in the same order.
+//! Fields that may be missing are `["null", T]` unions.
+//! The user data (`params` or `data`) is sent as JSON bytes next to its [DataType],
+//! `meta` and the other free form fields as JSON strings, like with the ProtoBuf serializer.
+//! Node `Buffer` data is seen by services as `{"type": "Buffer", "data": [..]}`.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use super::{buffer_data, PacketKind};
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0:?} packet is not an object")]
+    NotAnObject(PacketKind),
+
+    #[error("Packet ends in field '{0}'")]
+    UnexpectedEnd(&'static str),
+
+    #[error("Field '{0}' has a number longer than 64 bits")]
+    NumberTooLong(&'static str),
+
+    #[error("Field '{0}' has a negative length: {1}")]
+    NegativeLength(&'static str, i64),
+
+    #[error("Field '{0}' has an unknown union branch: {1}")]
+    UnknownBranch(&'static str, i64),
+
+    #[error("Field '{0}' is not UTF-8: {1}")]
+    NotUtf8(&'static str, std::str::Utf8Error),
+
+    #[error("{0} bytes left after the packet")]
+    TrailingBytes(usize),
+}
+
+pub(super) fn serialize<T: Serialize>(kind: PacketKind, msg: T) -> Result<Vec<u8>, Error> {
+    let packet = match serde_json::to_value(msg)? {
+        Value::Object(packet) => packet,
+        _ => return Err(Error::NotAnObject(kind)),
+    };
+
+    let mut bytes = vec![];
+    write_record(schema(kind), &packet, &mut bytes)?;
+
+    Ok(bytes)
+}
+
+pub(super) fn deserialize<T: DeserializeOwned>(kind: PacketKind, msg: &[u8]) -> Result<T, Error> {
+    let mut reader = Reader(msg);
+    let packet = reader.record(schema(kind))?;
+
+    if !reader.0.is_empty() {
+        return Err(Error::TrailingBytes(reader.0.len()));
+    }
+
+    Ok(serde_json::from_value(Value::Object(packet))?)
+}
+
+/// Type of a field, as written in the moleculerjs schemas
+#[derive(Clone, Copy, Debug)]
+enum Type {
+    Boolean,
+    Int,
+    Long,
+    Double,
+    String,
+    /// Any JSON value, sent as a string, ex: `meta`
+    Json,
+    /// `params` or `data`, `["null", "bytes"]` holding JSON or a `Buffer`
+    Data,
+    /// `int` with the [DataType] of the given field
+    FormatOf(&'static str),
+    Array(&'static Type),
+    Record(&'static [Field]),
+    /// `["null", type]`, null when the field is missing
+    Nullable(&'static Type),
+}
+
+type Field = (&'static str, Type);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DataType {
+    Undefined = 0,
+    Null = 1,
+    Json = 2,
+    Buffer = 3,
+}
+
+const NULLABLE_STRING: Type = Type::Nullable(&Type::String);
+const NULLABLE_BOOLEAN: Type = Type::Nullable(&Type::Boolean);
+const NULLABLE_INT: Type = Type::Nullable(&Type::Int);
+
+const EVENT: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("id", Type::String),
+    ("event", Type::String),
+    ("data", Type::Data),
+    ("dataType", Type::FormatOf("data")),
+    ("groups", Type::Nullable(&Type::Array(&Type::String))),
+    ("broadcast", Type::Boolean),
+    ("meta", Type::Json),
+    ("level", Type::Int),
+    ("tracing", NULLABLE_BOOLEAN),
+    ("parentID", NULLABLE_STRING),
+    ("requestID", NULLABLE_STRING),
+    ("stream", NULLABLE_BOOLEAN),
+    ("seq", NULLABLE_INT),
+    ("caller", NULLABLE_STRING),
+    ("needAck", NULLABLE_BOOLEAN),
+];
+
+const REQUEST: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("id", Type::String),
+    ("action", Type::String),
+    ("params", Type::Data),
+    ("paramsType", Type::FormatOf("params")),
+    ("meta", Type::Json),
+    ("timeout", Type::Nullable(&Type::Double)),
+    ("level", Type::Int),
+    ("tracing", NULLABLE_BOOLEAN),
+    ("parentID", NULLABLE_STRING),
+    ("requestID", NULLABLE_STRING),
+    ("stream", NULLABLE_BOOLEAN),
+    ("seq", NULLABLE_INT),
+    ("caller", NULLABLE_STRING),
+];
+
+const RESPONSE: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("id", Type::String),
+    ("success", Type::Boolean),
+    ("data", Type::Data),
+    ("dataType", Type::FormatOf("data")),
+    ("error", Type::Nullable(&Type::Json)),
+    ("meta", Type::Json),
+    ("stream", NULLABLE_BOOLEAN),
+    ("seq", NULLABLE_INT),
+];
+
+const DISCOVER: &[Field] = &[("ver", Type::String), ("sender", Type::String)];
+
+const INFO: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("services", Type::Json),
+    ("config", Type::Json),
+    ("ipList", Type::Array(&Type::String)),
+    ("hostname", Type::String),
+    ("client", Type::Record(CLIENT)),
+    ("seq", Type::Int),
+    ("instanceID", Type::String),
+    ("metadata", Type::Json),
+];
+
+const CLIENT: &[Field] = &[
+    ("type", Type::String),
+    ("version", Type::String),
+    ("langVersion", Type::String),
+];
+
+const DISCONNECT: &[Field] = &[("ver", Type::String), ("sender", Type::String)];
+
+const HEARTBEAT: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("cpu", Type::Double),
+];
+
+const PING: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("time", Type::Long),
+    ("id", NULLABLE_STRING),
+];
+
+const PONG: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("time", Type::Long),
+    ("arrived", Type::Long),
+    ("id", NULLABLE_STRING),
+];
+
+const GOSSIP_HELLO: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("host", Type::String),
+    ("port", Type::Int),
+];
+
+const GOSSIP: &[Field] = &[
+    ("ver", Type::String),
+    ("sender", Type::String),
+    ("online", Type::Nullable(&Type::Json)),
+    ("offline", Type::Nullable(&Type::Json)),
+];
+
+fn schema(kind: PacketKind) -> &'static [Field] {
+    match kind {
+        PacketKind::Event => EVENT,
+        PacketKind::Request => REQUEST,
+        PacketKind::Response => RESPONSE,
+        PacketKind::Discover => DISCOVER,
+        PacketKind::Info => INFO,
+        PacketKind::Disconnect => DISCONNECT,
+        PacketKind::Heartbeat => HEARTBEAT,
+        PacketKind::Ping => PING,
+        PacketKind::Pong => PONG,
+        PacketKind::GossipHello => GOSSIP_HELLO,
+        PacketKind::GossipRequest | PacketKind::GossipResponse => GOSSIP,
+    }
+}
+
+fn write_record(
+    fields: &[Field],
+    packet: &Map<String, Value>,
+    bytes: &mut Vec<u8>,
+) -> Result<(), Error> {
+    for (name, field_type) in fields {
+        // the data type is worked out from the data it describes
+        let value = match field_type {
+            Type::FormatOf(data) => packet.get(*data),
+            _ => packet.get(*name),
+        };
+
+        write(*field_type, value, bytes)?;
+    }
+
+    Ok(())
+}
+
+/// Missing values of fields that are not nullable are written with their default value
+fn write(field_type: Type, value: Option<&Value>, bytes: &mut Vec<u8>) -> Result<(), Error> {
+    match field_type {
+        Type::Boolean => bytes.push(value.and_then(Value::as_bool).unwrap_or_default() as u8),
+        Type::Int | Type::Long => write_long(integer(value), bytes),
+        Type::Double => {
+            let double = value.and_then(Value::as_f64).unwrap_or_default();
+            bytes.extend_from_slice(&double.to_le_bytes());
+        }
+        Type::String => write_bytes(
+            value.and_then(Value::as_str).unwrap_or_default().as_bytes(),
+            bytes,
+        ),
+        Type::Json => {
+            let json = match value {
+                Some(value) => serde_json::to_vec(value)?,
+                None => b"{}".to_vec(),
+            };
+            write_bytes(&json, bytes);
+        }
+        Type::Data => match value.filter(|value| !value.is_null()) {
+            Some(value) => {
+                let data = match buffer_data(value) {
+                    Some(data) => data,
+                    None => serde_json::to_vec(value)?,
+                };
+                write_long(1, bytes);
+                write_bytes(&data, bytes);
+            }
+            None => write_long(0, bytes),
+        },
+        Type::FormatOf(_) => write_long(data_type(value) as i64, bytes),
+        Type::Array(items) => {
+            let values = value.and_then(Value::as_array).map(Vec::as_slice);
+            let values = values.unwrap_or_default();
+
+            // a single block, ended by an empty one
+            if !values.is_empty() {
+                write_long(values.len() as i64, bytes);
+                for value in values {
+                    write(*items, Some(value), bytes)?;
+                }
+            }
+            write_long(0, bytes);
+        }
+        Type::Record(fields) => {
+            let empty = Map::new();
+            let record = value.and_then(Value::as_object).unwrap_or(&empty);
+            write_record(fields, record, bytes)?;
+        }
+        Type::Nullable(value_type) => match value.filter(|value| !value.is_null()) {
+            Some(value) => {
+                write_long(1, bytes);
+                write(*value_type, Some(value), bytes)?;
+            }
+            None => write_long(0, bytes),
+        },
+    }
+
+    Ok(())
+}
+
+fn integer(value: Option<&Value>) -> i64 {
+    match value {
+        Some(value) => value
+            .as_i64()
+            .or_else(|| value.as_f64().map(|float| float as i64))
+            .unwrap_or_default(),
+        None => 0,
+    }
+}
+
+/// Zigzag encoded variable length integer, used for `int`, `long`, lengths and union branches
+fn write_long(value: i64, bytes: &mut Vec<u8>) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn write_bytes(value: &[u8], bytes: &mut Vec<u8>) {
+    write_long(value.len() as i64, bytes);
+    bytes.extend_from_slice(value);
+}
+
+fn data_type(data: Option<&Value>) -> DataType {
+    match data {
+        None => DataType::Undefined,
+        Some(Value::Null) => DataType::Null,
+        Some(data) if buffer_data(data).is_some() => DataType::Buffer,
+        Some(_) => DataType::Json,
+    }
+}
+
+/// `None` when the data is undefined
+fn decode_data(bytes: Option<&[u8]>, data_type: i64) -> Result<Option<Value>, Error> {
+    let bytes = match bytes {
+        Some(bytes) => bytes,
+        None if data_type == DataType::Undefined as i64 => return Ok(None),
+        None => return Ok(Some(Value::Null)),
+    };
+
+    if data_type == DataType::Buffer as i64 {
+        Ok(Some(json!({ "type": "Buffer", "data": bytes })))
+    } else {
+        Ok(Some(serde_json::from_slice(bytes)?))
+    }
+}
+
+/// Reads a packet, the bytes left to read
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn record(&mut self, fields: &'static [Field]) -> Result<Map<String, Value>, Error> {
+        let mut record = Map::new();
+        let mut data = None;
+
+        for (name, field_type) in fields {
+            match field_type {
+                // kept until its data type is read
+                Type::Data => data = self.data(name)?,
+                Type::FormatOf(field) => {
+                    let data_type = self.long(name)?;
+                    if let Some(value) = decode_data(data.take(), data_type)? {
+                        record.insert(field.to_string(), value);
+                    }
+                }
+                _ => {
+                    let value = self.read(name, *field_type)?;
+                    record.insert(name.to_string(), value);
+                }
+            }
+        }
+
+        Ok(record)
+    }
+
+    fn read(&mut self, name: &'static str, field_type: Type) -> Result<Value, Error> {
+        let value = match field_type {
+            Type::Boolean => Value::Bool(self.take(name, 1)?[0] != 0),
+            Type::Int | Type::Long => json!(self.long(name)?),
+            Type::Double => {
+                let mut double = [0; 8];
+                double.copy_from_slice(self.take(name, 8)?);
+                json!(f64::from_le_bytes(double))
+            }
+            Type::String => Value::String(self.string(name)?.to_string()),
+            Type::Json => serde_json::from_str(self.string(name)?)?,
+            Type::Data => match self.data(name)? {
+                Some(data) => json!({ "type": "Buffer", "data": data }),
+                None => Value::Null,
+            },
+            Type::FormatOf(_) => json!(self.long(name)?),
+            Type::Array(items) => {
+                let mut values = vec![];
+
+                loop {
+                    let count = match self.long(name)? {
+                        0 => break,
+                        // followed by the size of the block in bytes
+                        count if count < 0 => {
+                            self.long(name)?;
+                            -count
+                        }
+                        count => count,
+                    };
+
+                    for _ in 0..count {
+                        values.push(self.read(name, *items)?);
+                    }
+                }
+
+                Value::Array(values)
+            }
+            Type::Record(fields) => Value::Object(self.record(fields)?),
+            Type::Nullable(value_type) => match self.long(name)? {
+                0 => Value::Null,
+                1 => self.read(name, *value_type)?,
+                branch => return Err(Error::UnknownBranch(name, branch)),
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn data(&mut self, name: &'static str) -> Result<Option<&'a [u8]>, Error> {
+        match self.long(name)? {
+            0 => Ok(None),
+            1 => Ok(Some(self.bytes(name)?)),
+            branch => Err(Error::UnknownBranch(name, branch)),
+        }
+    }
+
+    fn take(&mut self, name: &'static str, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::UnexpectedEnd(name));
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(taken)
+    }
+
+    fn long(&mut self, name: &'static str) -> Result<i64, Error> {
+        let mut value = 0_u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(name, 1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+            }
+        }
+
+        Err(Error::NumberTooLong(name))
+    }
+
+    fn bytes(&mut self, name: &'static str) -> Result<&'a [u8], Error> {
+        let len = self.long(name)?;
+        let len = usize::try_from(len).map_err(|_| Error::NegativeLength(name, len))?;
+
+        self.take(name, len)
+    }
+
+    fn string(&mut self, name: &'static str) -> Result<&'a str, Error> {
+        std::str::from_utf8(self.bytes(name)?).map_err(|err| Error::NotUtf8(name, err))
+    }
+}
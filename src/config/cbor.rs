@@ -0,0 +1,102 @@
+//! CBOR serializer, compatible with the moleculerjs `CBOR` serializer.
+//!
+//! Params are [serde_json::Value], which has no binary type. A Node `Buffer` arrives as a CBOR
+//! byte string and is handed to the services in the shape of `Buffer.toJSON()`:
+//! `{"type": "Buffer", "data": [1, 2, 3]}`. Values in that shape are sent back as byte strings.
+
+use ciborium::value::Value as Cbor;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Number, Value};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use super::buffer_data;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Encode(#[from] ciborium::ser::Error<std::io::Error>),
+
+    #[error(transparent)]
+    Decode(#[from] ciborium::de::Error<std::io::Error>),
+}
+
+pub(super) fn serialize<T: Serialize>(msg: T) -> Result<Vec<u8>, Error> {
+    let value = to_cbor(serde_json::to_value(msg)?);
+
+    let mut bytes = Vec::new();
+    ciborium::into_writer(&value, &mut bytes)?;
+
+    Ok(bytes)
+}
+
+pub(super) fn deserialize<T: DeserializeOwned>(msg: &[u8]) -> Result<T, Error> {
+    let value: Cbor = ciborium::from_reader(msg)?;
+    Ok(serde_json::from_value(from_cbor(value))?)
+}
+
+/// `{"type": "Buffer", "data": [..]}` maps become byte strings
+fn to_cbor(value: Value) -> Cbor {
+    if let Some(bytes) = buffer_data(&value) {
+        return Cbor::Bytes(bytes);
+    }
+
+    match value {
+        Value::Null => Cbor::Null,
+        Value::Bool(value) => Cbor::Bool(value),
+        Value::Number(number) => {
+            if let Some(number) = number.as_u64() {
+                Cbor::from(number)
+            } else if let Some(number) = number.as_i64() {
+                Cbor::from(number)
+            } else {
+                Cbor::Float(number.as_f64().unwrap_or_default())
+            }
+        }
+        Value::String(value) => Cbor::Text(value),
+        Value::Array(values) => Cbor::Array(values.into_iter().map(to_cbor).collect()),
+        Value::Object(entries) => Cbor::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Cbor::Text(key), to_cbor(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Byte strings become `{"type": "Buffer", "data": [..]}` maps
+fn from_cbor(value: Cbor) -> Value {
+    match value {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(value) => Value::Bool(value),
+        Cbor::Integer(number) => {
+            let number = i128::from(number);
+            match u64::try_from(number) {
+                Ok(number) => number.into(),
+                Err(_) => i64::try_from(number).unwrap_or_default().into(),
+            }
+        }
+        Cbor::Float(number) => Number::from_f64(number).map_or(Value::Null, Value::Number),
+        Cbor::Text(value) => Value::String(value),
+        Cbor::Bytes(bytes) => json!({ "type": "Buffer", "data": bytes }),
+        Cbor::Array(values) => Value::Array(values.into_iter().map(from_cbor).collect()),
+        Cbor::Map(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key_to_string(key), from_cbor(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        Cbor::Tag(_, value) => from_cbor(*value),
+        _ => Value::Null,
+    }
+}
+
+fn key_to_string(key: Cbor) -> String {
+    match key {
+        Cbor::Text(key) => key,
+        key => from_cbor(key).to_string(),
+    }
+}
@@ -0,0 +1,112 @@
+//! MsgPack encoding shared by the `MsgPack` and `Notepack` serializers, compatible with
+//! the moleculerjs `MsgPack` (msgpack5) and `Notepack` (notepack.io) serializers.
+//!
+//! msgpack5 and notepack.io read what the other writes, they only differ by the JS values packets
+//! don't hold. Node `Buffer` data travels as bin and is seen by services as
+//! `{"type": "Buffer", "data": [..]}`, like with the CBOR serializer. The notepack `Date`
+//! extension is read as its time in milliseconds and `undefined` as `null`.
+
+use rmpv::Value as Packed;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Number, Value};
+use std::convert::TryInto;
+use thiserror::Error;
+
+use super::buffer_data;
+
+/// Extension type notepack uses for `undefined` and `Date`
+const EXT_JS: i8 = 0;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Encode(#[from] rmpv::encode::Error),
+
+    #[error(transparent)]
+    Decode(#[from] rmpv::decode::Error),
+}
+
+pub(super) fn serialize<T: Serialize>(msg: T) -> Result<Vec<u8>, Error> {
+    let packed = pack(serde_json::to_value(msg)?);
+
+    let mut bytes = Vec::new();
+    rmpv::encode::write_value(&mut bytes, &packed)?;
+
+    Ok(bytes)
+}
+
+pub(super) fn deserialize<T: DeserializeOwned>(mut msg: &[u8]) -> Result<T, Error> {
+    let packed = rmpv::decode::read_value(&mut msg)?;
+    Ok(serde_json::from_value(unpack(packed))?)
+}
+
+fn pack(value: Value) -> Packed {
+    if let Some(bytes) = buffer_data(&value) {
+        return Packed::Binary(bytes);
+    }
+
+    match value {
+        Value::Null => Packed::Nil,
+        Value::Bool(value) => Packed::Boolean(value),
+        Value::Number(number) => {
+            if let Some(number) = number.as_u64() {
+                Packed::from(number)
+            } else if let Some(number) = number.as_i64() {
+                Packed::from(number)
+            } else {
+                Packed::F64(number.as_f64().unwrap_or_default())
+            }
+        }
+        Value::String(value) => Packed::from(value),
+        Value::Array(values) => Packed::Array(values.into_iter().map(pack).collect()),
+        Value::Object(entries) => Packed::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Packed::from(key), pack(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn unpack(packed: Packed) -> Value {
+    match packed {
+        Packed::Nil => Value::Null,
+        Packed::Boolean(value) => Value::Bool(value),
+        Packed::Integer(number) => match number.as_u64() {
+            Some(number) => number.into(),
+            None => number.as_i64().unwrap_or_default().into(),
+        },
+        Packed::F32(number) => float(number.into()),
+        Packed::F64(number) => float(number),
+        Packed::String(value) => Value::String(value.into_str().unwrap_or_default()),
+        Packed::Binary(bytes) => json!({ "type": "Buffer", "data": bytes }),
+        Packed::Array(values) => Value::Array(values.into_iter().map(unpack).collect()),
+        Packed::Map(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key_to_string(key), unpack(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        // a `Date` is its time in milliseconds
+        Packed::Ext(EXT_JS, data) if data.len() == 8 => {
+            let millis = f64::from_be_bytes(data.try_into().expect("checked length"));
+            float(millis)
+        }
+        // `undefined` and the extensions of other serializers
+        Packed::Ext(..) => Value::Null,
+    }
+}
+
+fn float(number: f64) -> Value {
+    Number::from_f64(number).map_or(Value::Null, Value::Number)
+}
+
+fn key_to_string(key: Packed) -> String {
+    match key {
+        Packed::String(key) => key.into_str().unwrap_or_default(),
+        key => key.to_string(),
+    }
+}
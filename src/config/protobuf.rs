@@ -0,0 +1,461 @@
+//! ProtoBuf serializer, compatible with the moleculerjs `ProtoBuf` serializer.
+//!
+//! Each packet kind has its own message, matching `packets.proto` of moleculerjs.
+//! The user data (`params` or `data`) is sent as JSON bytes next to its [DataType],
+//! `meta` and the other free form fields as JSON strings.
+//! Node `Buffer` data is seen by services as `{"type": "Buffer", "data": [..]}`, like with the CBOR serializer.
+
+use prost::Message;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use super::{buffer_data, PacketKind};
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Decode(#[from] prost::DecodeError),
+
+    #[error("{0:?} packet is not an object")]
+    NotAnObject(PacketKind),
+}
+
+pub(super) fn serialize<T: Serialize>(kind: PacketKind, msg: T) -> Result<Vec<u8>, Error> {
+    let packet = match serde_json::to_value(msg)? {
+        Value::Object(packet) => packet,
+        _ => return Err(Error::NotAnObject(kind)),
+    };
+
+    match kind {
+        PacketKind::Event => encode::<PacketEvent>(packet),
+        PacketKind::Request => encode::<PacketRequest>(packet),
+        PacketKind::Response => encode::<PacketResponse>(packet),
+        PacketKind::Discover => encode::<PacketDiscover>(packet),
+        PacketKind::Info => encode::<PacketInfo>(packet),
+        PacketKind::Disconnect => encode::<PacketDisconnect>(packet),
+        PacketKind::Heartbeat => encode::<PacketHeartbeat>(packet),
+        PacketKind::Ping => encode::<PacketPing>(packet),
+        PacketKind::Pong => encode::<PacketPong>(packet),
+        PacketKind::GossipHello => encode::<PacketGossipHello>(packet),
+        PacketKind::GossipRequest => encode::<PacketGossipRequest>(packet),
+        PacketKind::GossipResponse => encode::<PacketGossipResponse>(packet),
+    }
+}
+
+pub(super) fn deserialize<T: DeserializeOwned>(kind: PacketKind, msg: &[u8]) -> Result<T, Error> {
+    let packet = match kind {
+        PacketKind::Event => decode::<PacketEvent>(msg),
+        PacketKind::Request => decode::<PacketRequest>(msg),
+        PacketKind::Response => decode::<PacketResponse>(msg),
+        PacketKind::Discover => decode::<PacketDiscover>(msg),
+        PacketKind::Info => decode::<PacketInfo>(msg),
+        PacketKind::Disconnect => decode::<PacketDisconnect>(msg),
+        PacketKind::Heartbeat => decode::<PacketHeartbeat>(msg),
+        PacketKind::Ping => decode::<PacketPing>(msg),
+        PacketKind::Pong => decode::<PacketPong>(msg),
+        PacketKind::GossipHello => decode::<PacketGossipHello>(msg),
+        PacketKind::GossipRequest => decode::<PacketGossipRequest>(msg),
+        PacketKind::GossipResponse => decode::<PacketGossipResponse>(msg),
+    }?;
+
+    Ok(serde_json::from_value(Value::Object(packet))?)
+}
+
+fn encode<M: Schema>(mut packet: Map<String, Value>) -> Result<Vec<u8>, Error> {
+    let data = M::DATA_FIELD.map(|field| packet.remove(field));
+
+    for field in M::JSON_FIELDS {
+        if let Some(value) = packet.get_mut(*field).filter(|value| !value.is_null()) {
+            *value = Value::String(serde_json::to_string(value)?);
+        }
+    }
+
+    // proto3 has no null, missing fields are sent with their default value
+    packet.retain(|_, value| !value.is_null());
+
+    let mut message: M = serde_json::from_value(Value::Object(packet))?;
+
+    if let (Some(data), Some((bytes, data_type))) = (data, message.data_mut()) {
+        let (encoded, encoded_type) = encode_data(data)?;
+        *bytes = encoded;
+        *data_type = encoded_type as i32;
+    }
+
+    Ok(message.encode_to_vec())
+}
+
+fn decode<M: Schema>(msg: &[u8]) -> Result<Map<String, Value>, Error> {
+    let mut message = M::decode(msg)?;
+
+    let data = match message.data_mut() {
+        Some((bytes, data_type)) => decode_data(std::mem::take(bytes), *data_type)?,
+        None => None,
+    };
+
+    let mut packet = match serde_json::to_value(&message)? {
+        Value::Object(packet) => packet,
+        _ => unreachable!("packets are structs"),
+    };
+
+    // missing strings are received as empty strings
+    packet.retain(|_, value| !value.is_null() && value.as_str() != Some(""));
+
+    for field in M::JSON_FIELDS {
+        if let Some(value) = packet.get_mut(*field) {
+            if let Some(json) = value.as_str() {
+                *value = serde_json::from_str(json)?;
+            }
+        }
+    }
+
+    if let (Some(field), Some(data)) = (M::DATA_FIELD, data) {
+        packet.insert(field.to_string(), data);
+    }
+
+    Ok(packet)
+}
+
+fn encode_data(data: Option<Value>) -> Result<(Vec<u8>, DataType), Error> {
+    let data = match data {
+        None => return Ok((vec![], DataType::Undefined)),
+        Some(Value::Null) => return Ok((vec![], DataType::Null)),
+        Some(data) => data,
+    };
+
+    match buffer_data(&data) {
+        Some(bytes) => Ok((bytes, DataType::Buffer)),
+        None => Ok((serde_json::to_vec(&data)?, DataType::Json)),
+    }
+}
+
+/// `None` when the data is undefined
+fn decode_data(bytes: Vec<u8>, data_type: i32) -> Result<Option<Value>, Error> {
+    match DataType::try_from(data_type) {
+        Ok(DataType::Null) => Ok(Some(Value::Null)),
+        Ok(DataType::Buffer) => Ok(Some(json!({ "type": "Buffer", "data": bytes }))),
+        // nodes older than moleculerjs 0.14 don't send the data type
+        Ok(DataType::Undefined) if bytes.is_empty() => Ok(None),
+        _ => Ok(Some(serde_json::from_slice(&bytes)?)),
+    }
+}
+
+/// Message of a packet kind
+trait Schema: Message + Default + Serialize + DeserializeOwned {
+    /// Fields holding any JSON value, sent as JSON strings
+    const JSON_FIELDS: &'static [&'static str] = &[];
+
+    /// Field holding the user data, sent as bytes with its [DataType]
+    const DATA_FIELD: Option<&'static str> = None;
+
+    fn data_mut(&mut self) -> Option<(&mut Vec<u8>, &mut i32)> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum DataType {
+    Undefined = 0,
+    Null = 1,
+    Json = 2,
+    Buffer = 3,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PacketEvent {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(string, tag = "3")]
+    id: String,
+    #[prost(string, tag = "4")]
+    event: String,
+    #[prost(bytes = "vec", tag = "5")]
+    #[serde(skip)]
+    data: Vec<u8>,
+    #[prost(enumeration = "DataType", tag = "6")]
+    #[serde(skip)]
+    data_type: i32,
+    #[prost(string, repeated, tag = "7")]
+    groups: Vec<String>,
+    #[prost(bool, tag = "8")]
+    broadcast: bool,
+    #[prost(string, tag = "9")]
+    meta: String,
+    #[prost(int32, tag = "10")]
+    level: i32,
+    #[prost(bool, tag = "11")]
+    tracing: bool,
+    #[prost(string, tag = "12")]
+    #[serde(rename = "parentID")]
+    parent_id: String,
+    #[prost(string, tag = "13")]
+    #[serde(rename = "requestID")]
+    request_id: String,
+    #[prost(bool, tag = "14")]
+    stream: bool,
+    #[prost(int32, tag = "15")]
+    seq: i32,
+    #[prost(string, tag = "16")]
+    caller: String,
+    #[prost(bool, tag = "17")]
+    need_ack: bool,
+}
+
+impl Schema for PacketEvent {
+    const JSON_FIELDS: &'static [&'static str] = &["meta"];
+    const DATA_FIELD: Option<&'static str> = Some("data");
+
+    fn data_mut(&mut self) -> Option<(&mut Vec<u8>, &mut i32)> {
+        Some((&mut self.data, &mut self.data_type))
+    }
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PacketRequest {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(string, tag = "3")]
+    id: String,
+    #[prost(string, tag = "4")]
+    action: String,
+    #[prost(bytes = "vec", tag = "5")]
+    #[serde(skip)]
+    params: Vec<u8>,
+    #[prost(enumeration = "DataType", tag = "6")]
+    #[serde(skip)]
+    params_type: i32,
+    #[prost(string, tag = "7")]
+    meta: String,
+    #[prost(double, tag = "8")]
+    timeout: f64,
+    #[prost(int32, tag = "9")]
+    level: i32,
+    #[prost(bool, tag = "10")]
+    tracing: bool,
+    #[prost(string, tag = "11")]
+    #[serde(rename = "parentID")]
+    parent_id: String,
+    #[prost(string, tag = "12")]
+    #[serde(rename = "requestID")]
+    request_id: String,
+    #[prost(bool, tag = "13")]
+    stream: bool,
+    #[prost(int32, tag = "14")]
+    seq: i32,
+    #[prost(string, tag = "15")]
+    caller: String,
+}
+
+impl Schema for PacketRequest {
+    const JSON_FIELDS: &'static [&'static str] = &["meta"];
+    const DATA_FIELD: Option<&'static str> = Some("params");
+
+    fn data_mut(&mut self) -> Option<(&mut Vec<u8>, &mut i32)> {
+        Some((&mut self.params, &mut self.params_type))
+    }
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PacketResponse {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(string, tag = "3")]
+    id: String,
+    #[prost(bool, tag = "4")]
+    success: bool,
+    #[prost(bytes = "vec", tag = "5")]
+    #[serde(skip)]
+    data: Vec<u8>,
+    #[prost(enumeration = "DataType", tag = "6")]
+    #[serde(skip)]
+    data_type: i32,
+    #[prost(string, tag = "7")]
+    error: String,
+    #[prost(string, tag = "8")]
+    meta: String,
+    #[prost(bool, tag = "9")]
+    stream: bool,
+    #[prost(int32, tag = "10")]
+    seq: i32,
+}
+
+impl Schema for PacketResponse {
+    const JSON_FIELDS: &'static [&'static str] = &["meta", "error"];
+    const DATA_FIELD: Option<&'static str> = Some("data");
+
+    fn data_mut(&mut self) -> Option<(&mut Vec<u8>, &mut i32)> {
+        Some((&mut self.data, &mut self.data_type))
+    }
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketDiscover {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+}
+
+impl Schema for PacketDiscover {}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PacketInfo {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(string, tag = "3")]
+    services: String,
+    #[prost(string, tag = "4")]
+    config: String,
+    #[prost(string, repeated, tag = "5")]
+    ip_list: Vec<String>,
+    #[prost(string, tag = "6")]
+    hostname: String,
+    #[prost(message, optional, tag = "7")]
+    client: Option<Client>,
+    #[prost(int32, tag = "8")]
+    seq: i32,
+    #[prost(string, tag = "9")]
+    #[serde(rename = "instanceID")]
+    instance_id: String,
+    #[prost(string, tag = "10")]
+    metadata: String,
+}
+
+impl Schema for PacketInfo {
+    const JSON_FIELDS: &'static [&'static str] = &["services", "config", "metadata"];
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Client {
+    #[prost(string, tag = "1")]
+    #[serde(rename = "type")]
+    type_: String,
+    #[prost(string, tag = "2")]
+    version: String,
+    #[prost(string, tag = "3")]
+    lang_version: String,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketDisconnect {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+}
+
+impl Schema for PacketDisconnect {}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketHeartbeat {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(double, tag = "3")]
+    cpu: f64,
+}
+
+impl Schema for PacketHeartbeat {}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketPing {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(int64, tag = "3")]
+    time: i64,
+    #[prost(string, tag = "4")]
+    id: String,
+}
+
+impl Schema for PacketPing {}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketPong {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(int64, tag = "3")]
+    time: i64,
+    #[prost(int64, tag = "4")]
+    arrived: i64,
+    #[prost(string, tag = "5")]
+    id: String,
+}
+
+impl Schema for PacketPong {}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketGossipHello {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(string, tag = "3")]
+    host: String,
+    #[prost(int32, tag = "4")]
+    port: i32,
+}
+
+impl Schema for PacketGossipHello {}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketGossipRequest {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(string, tag = "3")]
+    online: String,
+    #[prost(string, tag = "4")]
+    offline: String,
+}
+
+impl Schema for PacketGossipRequest {
+    const JSON_FIELDS: &'static [&'static str] = &["online", "offline"];
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(default)]
+struct PacketGossipResponse {
+    #[prost(string, tag = "1")]
+    ver: String,
+    #[prost(string, tag = "2")]
+    sender: String,
+    #[prost(string, tag = "3")]
+    online: String,
+    #[prost(string, tag = "4")]
+    offline: String,
+}
+
+impl Schema for PacketGossipResponse {
+    const JSON_FIELDS: &'static [&'static str] = &["online", "offline"];
+}
@@ -3,7 +3,7 @@ Inspired and compatible with [Moleculer JS](https://github.com/moleculerjs/molec
 
 You can currently do all the basics of `emit`, `broadcast` and `call`.
 
-However it only works with the `NATS` transporter and `JSON` serializer/deserializer.
+However it only works with the `NATS`, `TCP`, `Redis`, `MQTT`, `AMQP`, `Kafka` and `Fake` (in-memory) transporters and `JSON`, `MsgPack`, `Notepack`, `CBOR`, `ProtoBuf` and `Avro` serializers/deserializers.
 
 ## Getting Started
 
@@ -63,7 +63,8 @@ fn print_hi(_ctx: EventContext) -> Result<(), Box<dyn Error>> {
 }
 
 // callback for second event, will be called whenever "printName" event is received
-fn print_name(ctx: EventContext) -> Result<(), Box<dyn Error>> {
+// callbacks can be sync or async
+async fn print_name(ctx: EventContext) -> Result<(), Box<dyn Error + Send + Sync>> {
     let msg: PrintNameMessage = serde_json::from_value(ctx.params)?;
 
     println!("Hello to: {} from Rust", msg.name);
@@ -72,15 +73,13 @@ fn print_name(ctx: EventContext) -> Result<(), Box<dyn Error>> {
 }
 
 // callback for math action
-fn math_add(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+fn math_add(ctx: ActionContext) -> Result<i32, Box<dyn Error>> {
     // get message decode using serde
-    let msg: ActionMessage = serde_json::from_value(ctx.params.clone())?;
+    let msg: ActionMessage = serde_json::from_value(ctx.params)?;
     let answer = msg.a + msg.b;
 
-    // serialize reply using serde and send reply
-    let _ = ctx.reply(answer.into());
-
-    Ok(())
+    // returned value is serialized using serde and sent as the reply
+    Ok(answer)
 }
 
 #[derive(Deserialize)]
@@ -106,26 +105,143 @@ pub mod service;
 
 mod broker;
 mod channels;
-mod nats;
+mod transporter;
 
 use act_zero::runtimes::tokio::spawn_actor;
 use act_zero::*;
 use config::Config;
 use serde_json::Value;
-use service::Service;
+use service::{CallOptions, CallOptionsBuilder, ParentContext, Service};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{self, Poll},
+    time::Duration,
+};
 use thiserror::Error;
 use tokio::sync::oneshot::{self, error};
 
-#[doc(hidden)]
+pub use channels::messages::MoleculerError;
+
+/// Errors returned when interacting with other nodes.
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Timeout reached waiting for response")]
+    #[error("Response channel closed before a response was received")]
     ReceiveError(#[from] error::RecvError),
 
+    /// No response was received before the request timeout.
+    #[error(transparent)]
+    RequestTimeout(#[from] RequestTimeoutError),
+
+    /// The call would exceed the `max_call_level` from the [Config].
+    #[error(transparent)]
+    MaxCallLevel(#[from] MaxCallLevelError),
+
+    /// No node is available that can handle the action.
+    #[error("Service '{0}' is not found")]
+    ServiceNotFound(String),
+
+    /// The node the call was targeted at does not have the action.
+    #[error("Service '{action}' is not available on '{node_id}' node")]
+    ServiceNotAvailable { action: String, node_id: String },
+
+    /// No node is available that has a handler for the event.
+    #[error("No handlers found for event '{0}'")]
+    EventHandlerNotFound(String),
+
+    /// The action failed on the node that handled the request.
+    #[error("Remote action failed: {0}")]
+    Remote(Box<MoleculerError>),
+
+    /// The transporter could not send the packet.
+    #[error(transparent)]
+    Publish(#[from] PublishError),
+
     #[error("Unknown error")]
     UnknownError,
 }
 
+impl Error {
+    /// Timeouts and remote errors marked as retryable can be retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::RequestTimeout(_) => true,
+            Error::Remote(error) => error.retryable,
+            _ => false,
+        }
+    }
+}
+
+impl From<MoleculerError> for Error {
+    fn from(error: MoleculerError) -> Self {
+        Error::Remote(Box::new(error))
+    }
+}
+
+/// The called action did not respond in time.
+#[derive(Error, Debug, Clone)]
+#[error("Request is timed out when call '{action}' action on '{node_id}' node after {elapsed:?}")]
+pub struct RequestTimeoutError {
+    pub action: String,
+    pub node_id: String,
+    pub elapsed: Duration,
+}
+
+impl From<RequestTimeoutError> for MoleculerError {
+    fn from(error: RequestTimeoutError) -> Self {
+        MoleculerError::new(error.to_string())
+            .set_name("RequestTimeoutError")
+            .set_code(504)
+            .set_type("REQUEST_TIMEOUT")
+            .set_data(serde_json::json!({"action": error.action, "nodeID": error.node_id}))
+            .set_retryable(true)
+    }
+}
+
+/// The transporter gave up sending a packet, ex: the connection is down and the outgoing buffer is full.
+#[derive(Error, Debug, Clone)]
+#[error("Unable to publish to '{topic}': {reason}")]
+pub struct PublishError {
+    pub topic: String,
+    pub reason: String,
+}
+
+/// The request level reached the `max_call_level` limit, usually caused by services calling each other in a loop.
+#[derive(Error, Debug, Clone)]
+#[error("Request level is reached the limit ({level}) on '{node_id}' node")]
+pub struct MaxCallLevelError {
+    pub node_id: String,
+    pub level: i32,
+}
+
+impl From<MaxCallLevelError> for MoleculerError {
+    fn from(error: MaxCallLevelError) -> Self {
+        MoleculerError::new(error.to_string())
+            .set_name("MaxCallLevelError")
+            .set_code(500)
+            .set_type("MAX_CALL_LEVEL")
+            .set_data(serde_json::json!({"nodeID": error.node_id, "level": error.level}))
+    }
+}
+
+pub(crate) type ResponseSender = oneshot::Sender<Result<Value, Error>>;
+pub(crate) type EmitSender = oneshot::Sender<Result<(), Error>>;
+
+/// Returned from [`emit()`][ServiceBroker::emit()] and [`broadcast()`][ServiceBroker::broadcast()].
+///
+/// The event is sent whether or not this is awaited, `await` it to find out if
+/// there was a node to send the event to.
+#[derive(Debug)]
+pub struct EmitHandle(oneshot::Receiver<Result<(), Error>>);
+
+impl Future for EmitHandle {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map(|result| result?)
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -154,7 +270,8 @@ pub struct ServiceBroker {
 pub type EventContext = service::Context<service::Event>;
 
 /// An alias to [service::Context\<service::Action>][service::Context].
-/// Send a response to a request using [`reply()`][service::Context::reply()].
+/// The value returned from the action callback is sent as the response,
+/// or send it yourself using [`reply()`][service::Context::reply()].
 pub type ActionContext = service::Context<service::Action>;
 
 impl ServiceBroker {
@@ -186,23 +303,88 @@ impl ServiceBroker {
     /// Request/Response style call
     /// Call an action directly with params serialized into
     /// [serde_json::Value](https://docs.rs/serde_json/1.0.64/serde_json/value/index.html) and `await` on the result
+    ///
+    /// If the action fails on the remote node the error is returned as [`Error::Remote`],
+    /// if no node has the action [`Error::ServiceNotFound`] is returned right away.
     pub async fn call<S: Into<String>>(self, action: S, params: Value) -> Result<Value, Error> {
-        let (tx, rx) = oneshot::channel();
+        self.call_with_options(action, params, CallOptionsBuilder::default().build())
+            .await
+    }
 
-        send!(self.addr.call(action.into(), params, tx));
-        let response_value = rx.await?;
+    /// Same as [`call()`][Self::call()], but overrides the `request_timeout` from the [Config].
+    ///
+    /// Returns [`Error::RequestTimeout`] if no response is received within the timeout.
+    pub async fn call_with_timeout<S: Into<String>>(
+        self,
+        action: S,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<Value, Error> {
+        let options = CallOptionsBuilder::default().timeout(timeout).build();
+        self.call_with_options(action, params, options).await
+    }
 
-        Ok(response_value)
+    /// Same as [`call()`][Self::call()], with [CallOptions] to target a node, add meta,
+    /// set the timeout, the number of retries or the parent context.
+    /// ```rust, ignore
+    /// let options = CallOptionsBuilder::default().node_id("node-1").retries(2u32).build();
+    /// let result = broker.call_with_options("math.add", json!{"a": 1, "b": c}, options).await?;
+    /// ```
+    pub async fn call_with_options<S: Into<String>>(
+        self,
+        action: S,
+        params: Value,
+        options: CallOptions,
+    ) -> Result<Value, Error> {
+        let action = action.into();
+        let mut retries_left = options.retries.unwrap_or(0);
+
+        loop {
+            let (tx, rx) = oneshot::channel();
+            send!(self
+                .addr
+                .call(action.clone(), params.clone(), options.clone(), tx));
+
+            match rx.await? {
+                Err(error) if retries_left > 0 && error.is_retryable() => {
+                    retries_left -= 1;
+                    log::warn!("Retrying call to '{}' after error: {}", &action, error);
+                }
+                result => return result,
+            }
+        }
     }
 
     /// Emits a balanced event to one of the nodes.
-    pub fn emit<S: Into<String>>(&self, event: S, params: Value) {
-        send!(self.addr.emit(event.into(), params))
+    ///
+    /// Await the returned [EmitHandle] to get [`Error::EventHandlerNotFound`] when no node handles the event.
+    pub fn emit<S: Into<String>>(&self, event: S, params: Value) -> EmitHandle {
+        self.emit_with_parent(event.into(), params, None, false)
     }
 
     /// Emits an event to all the nodes that can handle the event.
-    pub fn broadcast<S: Into<String>>(&self, event: S, params: Value) {
-        send!(self.addr.broadcast(event.into(), params))
+    ///
+    /// Await the returned [EmitHandle] to get [`Error::EventHandlerNotFound`] when no node handles the event.
+    pub fn broadcast<S: Into<String>>(&self, event: S, params: Value) -> EmitHandle {
+        self.emit_with_parent(event.into(), params, None, true)
+    }
+
+    pub(crate) fn emit_with_parent(
+        &self,
+        event: String,
+        params: Value,
+        parent: Option<ParentContext>,
+        broadcast: bool,
+    ) -> EmitHandle {
+        let (tx, rx) = oneshot::channel();
+
+        if broadcast {
+            send!(self.addr.broadcast(event, params, parent, tx));
+        } else {
+            send!(self.addr.emit(event, params, parent, tx));
+        }
+
+        EmitHandle(rx)
     }
 }
 
@@ -36,26 +36,141 @@
 //! }
 //!
 //! // callback for math action
-//! fn math_add(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+//! fn math_add(ctx: ActionContext) -> Result<serde_json::Value, Box<dyn Error>> {
 //!   /* compute value */
 //!
-//!   // send reply with value
-//!   ctx.reply(serde_json::json!{{}});
-//!   Ok(())
+//!   // returned value is sent as the reply
+//!   Ok(serde_json::json!{{}})
 //! }
 //! ```
 
+use derive_builder::Builder;
+use futures::future::{self, BoxFuture, FutureExt};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, marker::PhantomData};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
     channels::messages::incoming::{EventMessage, RequestMessage},
-    Error, ServiceBroker,
+    EmitHandle, Error, MoleculerError, ServiceBroker,
 };
 
+/// Error returned by a [Callback].
+pub type CallbackError = Box<dyn std::error::Error + Send + Sync>;
+
+type CallbackFuture = BoxFuture<'static, Result<Value, CallbackError>>;
+
 /// Function that is called when an [Event] or [Action] is received.
-pub type Callback<T> = fn(Context<T>) -> Result<(), Box<dyn std::error::Error>>;
+///
+/// Created from any sync or async function that takes a [Context], see [IntoCallback].
+pub struct Callback<T>(Arc<dyn Fn(Context<T>) -> CallbackFuture + Send + Sync>);
+
+impl<T> Callback<T> {
+    pub(crate) fn call(&self, ctx: Context<T>) -> CallbackFuture {
+        (self.0)(ctx)
+    }
+}
+
+impl<T> Clone for Callback<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> fmt::Debug for Callback<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+/// Marker for callbacks that run to completion synchronously.
+#[doc(hidden)]
+pub struct SyncCallback<R, E>(PhantomData<(R, E)>);
+
+/// Marker for callbacks that return a future.
+#[doc(hidden)]
+pub struct AsyncCallback<Fut, R, E>(PhantomData<(Fut, R, E)>);
+
+/// Implemented for every function or closure that can be used as a [Callback].
+///
+/// Sync callbacks have the signature `fn(Context<T>) -> Result<R, E>`,
+/// async callbacks are closures or functions returning a future of `Result<R, E>`.
+///
+/// For an [Action] the returned `R` is serialized and sent back to the caller,
+/// for an [Event] it is ignored.
+///
+/// ```rust
+/// use moleculer::{service::ActionBuilder, ActionContext};
+///
+/// let double = ActionBuilder::new("double")
+///     .add_callback(|ctx: ActionContext| async move {
+///         tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+///         let number: i64 = serde_json::from_value(ctx.params)?;
+///         Ok::<_, eyre::Report>(number * 2)
+///     })
+///     .build();
+/// ```
+pub trait IntoCallback<T, Marker> {
+    fn into_callback(self) -> Callback<T>;
+}
+
+impl<T, F, R, E> IntoCallback<T, SyncCallback<R, E>> for F
+where
+    T: Send + 'static,
+    F: Fn(Context<T>) -> Result<R, E> + Send + Sync + 'static,
+    R: Serialize,
+    E: Into<Box<dyn std::error::Error>>,
+{
+    fn into_callback(self) -> Callback<T> {
+        let callback = Arc::new(self);
+
+        Callback(Arc::new(move |ctx| {
+            let callback = Arc::clone(&callback);
+
+            // called when polled, so the function runs on the task of the callback
+            future::lazy(move |_| {
+                // sync errors are not required to be `Send`, keep only their message
+                callback(ctx)
+                    .map_err(|err| match err.into().downcast::<MoleculerError>() {
+                        Ok(err) => CallbackError::from(*err),
+                        Err(err) => CallbackError::from(err.to_string()),
+                    })
+                    .and_then(to_value)
+            })
+            .boxed()
+        }))
+    }
+}
+
+impl<T, F, Fut, R, E> IntoCallback<T, AsyncCallback<Fut, R, E>> for F
+where
+    F: Fn(Context<T>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, E>> + Send + 'static,
+    R: Serialize,
+    E: Into<CallbackError>,
+{
+    fn into_callback(self) -> Callback<T> {
+        Callback(Arc::new(move |ctx| {
+            self(ctx)
+                .map(|result| result.map_err(Into::into).and_then(to_value))
+                .boxed()
+        }))
+    }
+}
+
+fn to_value<R: Serialize>(value: R) -> Result<Value, CallbackError> {
+    serde_json::to_value(value).map_err(CallbackError::from)
+}
 
 /// Build using [ActionBuilder].
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -65,6 +180,8 @@ pub struct Action {
     params: Option<Value>,
     #[serde(skip)]
     pub(crate) callback: Option<Callback<Action>>,
+    #[serde(skip)]
+    pub(crate) service: Option<String>,
 }
 
 /// Builder for [Event].
@@ -81,8 +198,13 @@ pub struct Event {
     name: String,
     #[serde(default)]
     params: Option<Value>,
+    /// Set by moleculerjs services, events are balanced between the nodes of the same group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
     #[serde(skip)]
     pub(crate) callback: Option<Callback<Event>>,
+    #[serde(skip)]
+    pub(crate) service: Option<String>,
 }
 
 impl EventBuilder {
@@ -98,8 +220,9 @@ impl EventBuilder {
         self
     }
 
-    pub fn add_callback(mut self, callback: Callback<Event>) -> Self {
-        self.callback = Some(callback);
+    /// Set the function called when the event is received, can be sync or async.
+    pub fn add_callback<M>(mut self, callback: impl IntoCallback<Event, M>) -> Self {
+        self.callback = Some(callback.into_callback());
         self
     }
 
@@ -107,7 +230,9 @@ impl EventBuilder {
         Event {
             name: self.name,
             params: self.params,
+            group: None,
             callback: self.callback,
+            service: None,
         }
     }
 }
@@ -133,8 +258,9 @@ impl ActionBuilder {
         self
     }
 
-    pub fn add_callback(mut self, callback: Callback<Action>) -> Self {
-        self.callback = Some(callback);
+    /// Set the function called when the action is requested, can be sync or async.
+    pub fn add_callback<M>(mut self, callback: impl IntoCallback<Action, M>) -> Self {
+        self.callback = Some(callback.into_callback());
         self
     }
 
@@ -143,6 +269,7 @@ impl ActionBuilder {
             name: self.name,
             params: self.params,
             callback: self.callback,
+            service: None,
         }
     }
 }
@@ -177,6 +304,21 @@ impl Service {
         self
     }
 
+    /// Names of the events with their group, the service name unless the event sets its own group
+    pub(crate) fn event_groups(&self) -> impl Iterator<Item = (&String, &str)> {
+        self.events
+            .iter()
+            .map(move |(name, event)| (name, event.group.as_deref().unwrap_or(&self.name)))
+    }
+
+    /// Name including the version, ex: `v2.greeter`
+    pub(crate) fn full_name(&self) -> String {
+        match self.version {
+            Some(version) => format!("v{}.{}", version, self.name),
+            None => self.name.clone(),
+        }
+    }
+
     pub fn add_action(mut self, action: Action) -> Self {
         self.actions.insert(action.name.clone(), action);
         self
@@ -197,7 +339,8 @@ pub enum EventType {
 
 /// Context is available in all callbacks.
 ///
-/// In an [action][Action] context you can send a response to the request using [`reply()`][Self::reply()]
+/// In an [action][Action] the value returned from the callback is sent as the response to the request.
+/// Alternatively you can send the response yourself using [`reply()`][Self::reply()]
 ///
 /// In all contexts [`emit()`][Self::emit()], [`broadcast()`][Self::broadcast()] and
 /// [`call()`][Self::call()] are available
@@ -207,6 +350,8 @@ pub struct Context<T> {
     pub id: String,
     pub broker: ServiceBroker,
     pub node_id: String,
+    /// Name of the service handling the event or action.
+    pub service: Option<String>,
     pub action: Option<String>,
 
     pub event_name: Option<String>,
@@ -222,10 +367,17 @@ pub struct Context<T> {
     pub locals: Option<Value>,
 
     pub level: i32,
+
+    pub(crate) replied: Arc<AtomicBool>,
+    pub(crate) deadline: Option<Instant>,
 }
 
 impl Context<Event> {
-    pub(crate) fn new(event_message: EventMessage, service_broker: ServiceBroker) -> Self {
+    pub(crate) fn new(
+        event_message: EventMessage,
+        service: Option<String>,
+        service_broker: ServiceBroker,
+    ) -> Self {
         let event_type = if event_message.broadcast.unwrap_or(false) {
             EventType::Broadcast
         } else {
@@ -236,6 +388,7 @@ impl Context<Event> {
             phantom: PhantomData,
 
             broker: service_broker,
+            service,
             id: event_message.id,
             params: event_message.data,
 
@@ -254,17 +407,32 @@ impl Context<Event> {
             level: event_message.level,
 
             locals: None,
+
+            replied: Arc::new(AtomicBool::new(false)),
+            deadline: None,
         }
     }
 }
 
 impl Context<Action> {
-    pub(crate) fn new(request_message: RequestMessage, service_broker: ServiceBroker) -> Self {
+    pub(crate) fn new(
+        request_message: RequestMessage,
+        service: Option<String>,
+        service_broker: ServiceBroker,
+    ) -> Self {
+        // the caller stops waiting for a response after the timeout
+        let deadline = if request_message.timeout > 0.0 {
+            Some(Instant::now() + Duration::from_millis(request_message.timeout as u64))
+        } else {
+            None
+        };
+
         Self {
             phantom: PhantomData,
 
             broker: service_broker,
-            id: request_message.request_id.clone(),
+            service,
+            id: request_message.id,
             params: request_message.params,
 
             action: Some(request_message.action),
@@ -279,13 +447,24 @@ impl Context<Action> {
             request_id: Some(request_message.request_id),
 
             meta: request_message.meta,
-            level: 1,
+            level: request_message.level,
 
             locals: None,
+
+            replied: Arc::new(AtomicBool::new(false)),
+            deadline,
         }
     }
 
+    /// Send a response to the request before the callback returns.
+    ///
+    /// Only the first response is sent, once replied the value returned from the callback is ignored.
     pub fn reply(&self, params: Value) {
+        if self.replied.swap(true, Ordering::SeqCst) {
+            log::warn!("Already replied to request '{}', ignoring reply", self.id);
+            return;
+        }
+
         act_zero::send!(self
             .broker
             .addr
@@ -294,15 +473,105 @@ impl Context<Action> {
 }
 
 impl<T> Context<T> {
-    pub fn emit<S: Into<String>>(&self, event: S, params: Value) {
-        self.broker.emit(event, params)
+    /// Emit an event with this context as the parent context.
+    pub fn emit<S: Into<String>>(&self, event: S, params: Value) -> EmitHandle {
+        self.broker
+            .emit_with_parent(event.into(), params, Some(self.into()), false)
     }
 
-    pub fn broadcast<S: Into<String>>(&self, event: S, params: Value) {
-        self.broker.broadcast(event, params)
+    /// Broadcast an event with this context as the parent context.
+    pub fn broadcast<S: Into<String>>(&self, event: S, params: Value) -> EmitHandle {
+        self.broker
+            .emit_with_parent(event.into(), params, Some(self.into()), true)
     }
 
+    /// Call an action with this context as the parent context, see [`CallOptionsBuilder::parent_ctx()`].
     pub async fn call<S: Into<String>>(self, action: S, params: Value) -> Result<Value, Error> {
-        self.broker.call(action, params).await
+        self.call_with_options(action, params, CallOptionsBuilder::default().build())
+            .await
+    }
+
+    /// Same as [`call()`][Self::call()] with [CallOptions], the parent context is always this context.
+    pub async fn call_with_options<S: Into<String>>(
+        self,
+        action: S,
+        params: Value,
+        options: CallOptions,
+    ) -> Result<Value, Error> {
+        let options = CallOptions {
+            parent_ctx: Some(ParentContext::from(&self)),
+            ..options
+        };
+
+        self.broker.call_with_options(action, params, options).await
+    }
+}
+
+/// Options for a single call, build using [CallOptionsBuilder].
+///
+/// ```rust
+/// use std::time::Duration;
+/// use moleculer::service::CallOptionsBuilder;
+///
+/// let options = CallOptionsBuilder::default()
+///     .node_id("node-1")
+///     .meta(serde_json::json!({"user": "john"}))
+///     .timeout(Duration::from_secs(5))
+///     .retries(3u32)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default, Builder)]
+#[builder(pattern = "owned")]
+#[builder(build_fn(name = "build_private", private))]
+#[builder(default, setter(into, strip_option))]
+pub struct CallOptions {
+    /// Call the action on this node instead of balancing between all nodes.
+    pub(crate) node_id: Option<String>,
+    /// Merged into the meta of the request, overrides the meta of the parent context.
+    pub(crate) meta: Option<Value>,
+    /// Overrides the `request_timeout` from the [Config][crate::config::Config].
+    pub(crate) timeout: Option<Duration>,
+    /// Number of times to retry the call when it fails with a retryable error.
+    pub(crate) retries: Option<u32>,
+    #[builder(setter(custom))]
+    pub(crate) parent_ctx: Option<ParentContext>,
+}
+
+impl CallOptionsBuilder {
+    /// Make the call part of the call chain of the context. The `meta`, `level` and `request_id`
+    /// are carried over and the remaining time of the parent request is used as the timeout.
+    pub fn parent_ctx<T>(mut self, ctx: &Context<T>) -> Self {
+        self.parent_ctx = Some(Some(ParentContext::from(ctx)));
+        self
+    }
+
+    pub fn build(self) -> CallOptions {
+        self.build_private()
+            .expect("will always work because all fields have defaults")
+    }
+}
+
+/// The parts of a [Context] that are carried over to the requests and events sent from it.
+#[derive(Debug, Clone)]
+pub(crate) struct ParentContext {
+    pub(crate) id: String,
+    pub(crate) request_id: String,
+    pub(crate) level: i32,
+    pub(crate) meta: Value,
+    pub(crate) caller: Option<String>,
+    pub(crate) deadline: Option<Instant>,
+}
+
+impl<T> From<&Context<T>> for ParentContext {
+    fn from(ctx: &Context<T>) -> Self {
+        Self {
+            id: ctx.id.clone(),
+            // the context is the start of the call chain if it does not have a request id
+            request_id: ctx.request_id.clone().unwrap_or_else(|| ctx.id.clone()),
+            level: ctx.level,
+            meta: ctx.meta.clone(),
+            caller: ctx.service.clone(),
+            deadline: ctx.deadline,
+        }
     }
 }
@@ -0,0 +1,197 @@
+#[cfg(feature = "amqp")]
+pub(crate) mod amqp;
+pub(crate) mod fake;
+#[cfg(feature = "kafka")]
+pub(crate) mod kafka;
+#[cfg(feature = "mqtt")]
+pub(crate) mod mqtt;
+pub(crate) mod nats;
+#[cfg(feature = "redis")]
+pub(crate) mod redis;
+pub(crate) mod tcp;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{
+    channel::mpsc,
+    future::{BoxFuture, Future},
+    stream::BoxStream,
+    FutureExt as _, StreamExt as _,
+};
+use thiserror::Error;
+
+use crate::config::{self, Config};
+
+pub(crate) type Result<T> = std::result::Result<T, self::Error>;
+
+/// Stream of the payloads of messages received on a subscribed topic.
+pub(crate) type Subscription = BoxStream<'static, Bytes>;
+
+/// Stream of the messages received on a subscribed topic, each one acknowledged once handled.
+pub(crate) type AckedSubscription = BoxStream<'static, (Bytes, Ack)>;
+
+/// Acknowledges a received message to the server, once the broker is done with it.
+///
+/// Dropping it without calling [Ack::ack()] leaves the message unacknowledged,
+/// the server delivers it again once the connection is closed.
+pub(crate) struct Ack(Option<BoxFuture<'static, ()>>);
+
+impl Ack {
+    pub(crate) fn new(ack: impl Future<Output = ()> + Send + 'static) -> Ack {
+        Ack(Some(ack.boxed()))
+    }
+
+    /// For messages that are not acknowledged
+    pub(crate) fn none() -> Ack {
+        Ack(None)
+    }
+
+    pub(crate) async fn ack(self) {
+        if let Some(ack) = self.0 {
+            ack.await;
+        }
+    }
+}
+
+/// Shared connection used by all the channels.
+pub(crate) type Conn = Arc<dyn Transporter>;
+
+/// Change of the connection state, for transporters that reconnect by themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConnectionEvent {
+    /// Connected again after losing the connection
+    Reconnected,
+    Disconnected,
+}
+
+/// Stream of the connection state changes of a transporter.
+pub(crate) type ConnectionEvents = BoxStream<'static, ConnectionEvent>;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error(transparent)]
+    Nats(#[from] nats::Error),
+
+    #[error(transparent)]
+    Tcp(#[from] tcp::Error),
+
+    #[cfg(feature = "redis")]
+    #[error(transparent)]
+    Redis(#[from] redis::Error),
+
+    #[cfg(feature = "mqtt")]
+    #[error(transparent)]
+    Mqtt(#[from] mqtt::Error),
+
+    #[cfg(feature = "amqp")]
+    #[error(transparent)]
+    Amqp(#[from] amqp::Error),
+
+    #[cfg(feature = "kafka")]
+    #[error(transparent)]
+    Kafka(#[from] kafka::Error),
+}
+
+/// Moves packets between nodes, implemented by each transporter backend.
+///
+/// Topics are the channel names from [config::Channel], ex: `MOL.INFO.node-1`.
+#[async_trait]
+pub(crate) trait Transporter: Send + Sync {
+    async fn connect(config: &Config) -> Result<Self>
+    where
+        Self: Sized;
+
+    async fn subscribe(&self, topic: &str) -> Result<Subscription>;
+
+    /// Subscribe to a topic whose messages are acknowledged once handled, used for the `REQ` and `EVENT` topics.
+    /// Messages are not acknowledged unless the transporter overrides it.
+    async fn subscribe_acked(&self, topic: &str) -> Result<AckedSubscription> {
+        let subscription = self.subscribe(topic).await?;
+        Ok(subscription.map(|message| (message, Ack::none())).boxed())
+    }
+
+    /// Subscribe to a topic shared by the nodes of a group, each message is received by a single node.
+    /// Only used for the `REQB` and `EVENTB` topics when [Transporter::has_built_in_balancer()].
+    async fn subscribe_balanced(&self, topic: &str, _group: &str) -> Result<AckedSubscription> {
+        self.subscribe_acked(topic).await
+    }
+
+    /// Can balance requests and events between nodes, used when `disable_balancer` is set.
+    fn has_built_in_balancer(&self) -> bool {
+        false
+    }
+
+    /// Stores the `EVENTB` packets until a node of their group reads them, even while no node of the group is up.
+    /// Events are then always emitted to the groups, as if `disable_balancer` was set for events.
+    fn stores_events(&self) -> bool {
+        false
+    }
+
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> Result<()>;
+
+    /// Connection state changes, only returned on the first call.
+    /// `None` for transporters that don't report them.
+    fn connection_events(&self) -> Option<ConnectionEvents> {
+        None
+    }
+
+    async fn disconnect(&self) -> Result<()>;
+}
+
+/// Connect using the transporter selected in the [Config].
+pub(crate) async fn connect(config: &Config) -> Result<Conn> {
+    let conn: Conn = match &config.transporter {
+        config::Transporter::Nats(_) => Arc::new(nats::Conn::connect(config).await?),
+        config::Transporter::Fake | config::Transporter::FakeWithLink(_) => {
+            Arc::new(fake::Conn::connect(config).await?)
+        }
+        config::Transporter::Tcp(_) => Arc::new(tcp::Conn::connect(config).await?),
+        #[cfg(feature = "redis")]
+        config::Transporter::Redis(_) => Arc::new(redis::Conn::connect(config).await?),
+        #[cfg(feature = "mqtt")]
+        config::Transporter::Mqtt(_) => Arc::new(mqtt::Conn::connect(config).await?),
+        #[cfg(feature = "amqp")]
+        config::Transporter::Amqp(_) => Arc::new(amqp::Conn::connect(config).await?),
+        #[cfg(feature = "kafka")]
+        config::Transporter::Kafka(_) => Arc::new(kafka::Conn::connect(config).await?),
+    };
+
+    Ok(conn)
+}
+
+/// Subscriptions of a transporter that receives the messages of all topics on a single connection.
+#[derive(Default)]
+pub(crate) struct Subscribers(Mutex<HashMap<String, Vec<mpsc::UnboundedSender<Bytes>>>>);
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self, topic: &str) -> Subscription {
+        let (tx, rx) = mpsc::unbounded();
+
+        self.0
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_default()
+            .push(tx);
+
+        rx.boxed()
+    }
+
+    /// Hand a message to the subscriptions of the topic
+    pub(crate) fn publish(&self, topic: &str, message: Bytes) {
+        if let Some(subscribers) = self.0.lock().unwrap().get_mut(topic) {
+            // drop subscribers whose stream has been dropped
+            subscribers.retain(|tx| tx.unbounded_send(message.clone()).is_ok());
+        }
+    }
+
+    /// End all the subscriptions
+    pub(crate) fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,302 @@
+use async_trait::async_trait;
+use futures::StreamExt as _;
+use lapin::{
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicQosOptions,
+        ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable},
+    BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind,
+};
+use log::{debug, error};
+use std::{collections::HashSet, sync::Mutex};
+use thiserror::Error;
+
+use super::{Ack, AckedSubscription, Subscription, Transporter};
+use crate::config::{self, AmqpOptions, Config};
+
+type Result<T> = std::result::Result<T, self::Error>;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("Unable to connect to AMQP: {0}")]
+    UnableToConnect(lapin::Error),
+
+    #[error("Unable to subscribe to queue ({0}): {1}")]
+    UnableToSubscribe(String, lapin::Error),
+
+    #[error("Unable to publish to ({0}): {1}")]
+    PublishFailed(String, lapin::Error),
+
+    #[error("Unable to close the AMQP connection: {0}")]
+    CloseFailed(lapin::Error),
+}
+
+/// Works like the moleculerjs AMQP transporter:
+/// - broadcast packets go through a fanout exchange per topic, each node binds its own queue to it
+/// - targeted packets are sent straight to the queue of the target node, ex: `MOL.REQ.node-1`
+/// - balanced packets (`MOL.REQB.<action>`, `MOL.EVENTB.<group>.<event>`) use durable queues
+///   shared by all the nodes, so packets sent while the nodes restart are not lost
+pub(crate) struct Conn {
+    connection: Connection,
+    channel: Channel,
+    options: AmqpOptions,
+    node_id: String,
+    prefix: String,
+
+    /// exchanges already declared on the channel
+    exchanges: Mutex<HashSet<String>>,
+}
+
+/// How a packet is routed, depends on the command of the topic
+enum Route {
+    /// fanout exchange named after the topic, each node has its own queue
+    Broadcast,
+    /// queue named after the topic, read by a single node
+    Targeted,
+    /// durable queue named after the topic, shared by all the nodes
+    Balanced,
+}
+
+impl Conn {
+    pub(crate) async fn new(options: &AmqpOptions, config: &Config) -> Result<Conn> {
+        let connection = Connection::connect(&options.url, ConnectionProperties::default())
+            .await
+            .map_err(Error::UnableToConnect)?;
+
+        let channel = connection
+            .create_channel()
+            .await
+            .map_err(Error::UnableToConnect)?;
+
+        channel
+            .basic_qos(options.prefetch, BasicQosOptions::default())
+            .await
+            .map_err(Error::UnableToConnect)?;
+
+        Ok(Conn {
+            connection,
+            channel,
+            options: options.clone(),
+            node_id: config.node_id.clone(),
+            prefix: config::mol(config).into_owned(),
+            exchanges: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Split a topic into its command and route, ex: `MOL.REQ.node-1` is a targeted `REQ`
+    fn route<'a>(&self, topic: &'a str) -> (&'a str, Route) {
+        let topic = topic
+            .strip_prefix(self.prefix.as_str())
+            .and_then(|topic| topic.strip_prefix('.'))
+            .unwrap_or(topic);
+
+        match topic.split_once('.') {
+            Some((command @ ("REQB" | "EVENTB"), _)) => (command, Route::Balanced),
+            Some((command, _)) => (command, Route::Targeted),
+            None => (topic, Route::Broadcast),
+        }
+    }
+
+    fn queue_options(&self, command: &str) -> (QueueDeclareOptions, FieldTable) {
+        let mut arguments = FieldTable::default();
+
+        let expires = |arguments: &mut FieldTable| {
+            if let Some(expires) = self.options.auto_delete_queues {
+                let expires = AMQPValue::LongUInt(expires.as_millis() as u32);
+                arguments.insert("x-expires".into(), expires);
+            }
+        };
+
+        let auto_delete = match command {
+            "REQ" | "RES" => {
+                expires(&mut arguments);
+                false
+            }
+            "EVENT" | "EVENTB" => {
+                expires(&mut arguments);
+
+                if let Some(ttl) = self.options.event_time_to_live {
+                    let ttl = AMQPValue::LongUInt(ttl.as_millis() as u32);
+                    arguments.insert("x-message-ttl".into(), ttl);
+                }
+                false
+            }
+            "REQB" => false,
+
+            // INFO, DISCOVER, HEARTBEAT, DISCONNECT, PING and PONG are only useful while the node is up
+            _ => true,
+        };
+
+        let options = QueueDeclareOptions {
+            durable: true,
+            auto_delete,
+            ..QueueDeclareOptions::default()
+        };
+
+        (options, arguments)
+    }
+
+    async fn declare_exchange(&self, exchange: &str) -> std::result::Result<(), lapin::Error> {
+        if self.exchanges.lock().unwrap().contains(exchange) {
+            return Ok(());
+        }
+
+        let options = ExchangeDeclareOptions {
+            durable: true,
+            ..ExchangeDeclareOptions::default()
+        };
+
+        self.channel
+            .exchange_declare(
+                exchange,
+                ExchangeKind::Fanout,
+                options,
+                FieldTable::default(),
+            )
+            .await?;
+
+        self.exchanges.lock().unwrap().insert(exchange.to_string());
+        Ok(())
+    }
+
+    async fn consume(&self, topic: &str) -> std::result::Result<AckedSubscription, lapin::Error> {
+        let (command, route) = self.route(topic);
+
+        let queue = match route {
+            Route::Broadcast => format!("{}.{}", topic, self.node_id),
+            Route::Targeted | Route::Balanced => topic.to_string(),
+        };
+
+        let (queue_options, arguments) = self.queue_options(command);
+        self.channel
+            .queue_declare(&queue, queue_options, arguments)
+            .await?;
+
+        if let Route::Broadcast = route {
+            self.declare_exchange(topic).await?;
+            self.channel
+                .queue_bind(
+                    &queue,
+                    topic,
+                    "",
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+        }
+
+        // requests and balanced packets are acknowledged once handled,
+        // so they stay in their queue until a node is done with them
+        let acknowledge = self.options.acknowledge
+            && matches!((command, &route), ("REQ", _) | (_, Route::Balanced));
+
+        let consume_options = BasicConsumeOptions {
+            no_ack: !acknowledge,
+            ..BasicConsumeOptions::default()
+        };
+
+        let consumer = self
+            .channel
+            .basic_consume(&queue, &queue, consume_options, FieldTable::default())
+            .await?;
+
+        debug!("Consuming AMQP queue: {}", queue);
+
+        let subscription = consumer.filter_map(move |delivery| async move {
+            let delivery = match delivery {
+                Ok(delivery) => delivery,
+                Err(err) => {
+                    error!("Unable to receive AMQP message: {}", err);
+                    return None;
+                }
+            };
+
+            let ack = if acknowledge {
+                let acker = delivery.acker;
+                Ack::new(async move {
+                    if let Err(err) = acker.ack(BasicAckOptions::default()).await {
+                        error!("Unable to acknowledge AMQP message: {}", err);
+                    }
+                })
+            } else {
+                Ack::none()
+            };
+
+            Some((delivery.data.into(), ack))
+        });
+
+        Ok(subscription.boxed())
+    }
+
+    /// Requests and balanced packets are stored on disk by the server, so they survive its restart
+    fn properties(&self, topic: &str) -> BasicProperties {
+        match self.route(topic) {
+            ("REQ", _) | (_, Route::Balanced) => BasicProperties::default().with_delivery_mode(2),
+            _ => BasicProperties::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transporter for Conn {
+    async fn connect(config: &Config) -> super::Result<Self> {
+        match &config.transporter {
+            config::Transporter::Amqp(options) => Ok(Conn::new(options, config).await?),
+            _ => unreachable!("AMQP transporter used without an AMQP config"),
+        }
+    }
+
+    async fn subscribe(&self, topic: &str) -> super::Result<Subscription> {
+        let subscription = self.subscribe_acked(topic).await?;
+        Ok(subscription.map(|(message, _)| message).boxed())
+    }
+
+    async fn subscribe_acked(&self, topic: &str) -> super::Result<AckedSubscription> {
+        Ok(self
+            .consume(topic)
+            .await
+            .map_err(|e| Error::UnableToSubscribe(topic.to_string(), e))?)
+    }
+
+    /// `REQB` and `EVENTB` queues are already shared by the nodes
+    fn has_built_in_balancer(&self) -> bool {
+        true
+    }
+
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> super::Result<()> {
+        let (exchange, routing_key) = match self.route(topic) {
+            (_, Route::Broadcast) => {
+                self.declare_exchange(topic)
+                    .await
+                    .map_err(|e| Error::PublishFailed(topic.to_string(), e))?;
+
+                (topic, "")
+            }
+            // the default exchange routes to the queue with the same name
+            (_, Route::Targeted | Route::Balanced) => ("", topic),
+        };
+
+        self.channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                &message,
+                self.properties(topic),
+            )
+            .await
+            .map_err(|e| Error::PublishFailed(topic.to_string(), e))?;
+
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> super::Result<()> {
+        self.connection
+            .close(200, "disconnect")
+            .await
+            .map_err(Error::CloseFailed)?;
+
+        Ok(())
+    }
+}
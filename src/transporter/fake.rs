@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{channel::mpsc, StreamExt as _};
+use rand::seq::SliceRandom as _;
+use uuid::Uuid;
+
+use super::{
+    Ack, AckedSubscription, ConnectionEvent, ConnectionEvents, Result, Subscription, Transporter,
+};
+use crate::config::{self, Config};
+
+type ConnId = Uuid;
+
+/// Subscribers for each topic, shared by every fake connection in the process
+#[derive(Default)]
+struct Bus {
+    topics: HashMap<String, Vec<Subscriber>>,
+}
+
+struct Subscriber {
+    conn: ConnId,
+    /// queue group, each message goes to a single subscriber of the group
+    group: Option<String>,
+    link: FakeLink,
+    tx: mpsc::UnboundedSender<Bytes>,
+}
+
+fn bus() -> &'static Mutex<Bus> {
+    static BUS: OnceLock<Mutex<Bus>> = OnceLock::new();
+    BUS.get_or_init(Default::default)
+}
+
+/// Connection of a broker to the in-memory transporter, cut and restored to test reconnections,
+/// see [Transporter::fake_with_link()](config::Transporter::fake_with_link()).
+#[derive(Clone, Default)]
+pub struct FakeLink(Arc<Mutex<Link>>);
+
+#[derive(Default)]
+struct Link {
+    down: bool,
+    events: Vec<mpsc::UnboundedSender<ConnectionEvent>>,
+}
+
+impl FakeLink {
+    /// Cut the connection, packets published or delivered meanwhile are lost
+    pub fn disconnect(&self) {
+        self.set_down(true, ConnectionEvent::Disconnected);
+    }
+
+    /// Restore the connection, the broker then sends its INFO and a DISCOVER again
+    pub fn reconnect(&self) {
+        self.set_down(false, ConnectionEvent::Reconnected);
+    }
+
+    fn set_down(&self, down: bool, event: ConnectionEvent) {
+        let mut link = self.0.lock().unwrap();
+        if link.down == down {
+            return;
+        }
+
+        link.down = down;
+        link.events
+            .retain(|events| events.unbounded_send(event).is_ok());
+    }
+
+    fn is_down(&self) -> bool {
+        self.0.lock().unwrap().down
+    }
+}
+
+impl std::fmt::Debug for FakeLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FakeLink")
+            .field("down", &self.is_down())
+            .finish()
+    }
+}
+
+/// In-memory transporter, all brokers in the same process using it can talk to each other.
+/// Brokers are kept apart by their `namespace`, just like on NATS.
+/// Balanced topics use queue groups like NATS, so `disable_balancer` can be tested without a server.
+pub(crate) struct Conn {
+    id: ConnId,
+    link: FakeLink,
+
+    /// taken by the first call to [Transporter::connection_events()]
+    connection_events: Mutex<Option<mpsc::UnboundedReceiver<ConnectionEvent>>>,
+}
+
+impl Conn {
+    fn add_subscriber(&self, topic: &str, group: Option<String>) -> mpsc::UnboundedReceiver<Bytes> {
+        let (tx, rx) = mpsc::unbounded();
+
+        bus()
+            .lock()
+            .unwrap()
+            .topics
+            .entry(topic.to_string())
+            .or_default()
+            .push(Subscriber {
+                conn: self.id,
+                group,
+                link: self.link.clone(),
+                tx,
+            });
+
+        rx
+    }
+}
+
+#[async_trait]
+impl Transporter for Conn {
+    async fn connect(config: &Config) -> Result<Self> {
+        let link = match &config.transporter {
+            config::Transporter::FakeWithLink(link) => link.clone(),
+            _ => FakeLink::default(),
+        };
+
+        let (events_tx, events) = mpsc::unbounded();
+        link.0.lock().unwrap().events.push(events_tx);
+
+        Ok(Conn {
+            id: Uuid::new_v4(),
+            link,
+            connection_events: Mutex::new(Some(events)),
+        })
+    }
+
+    async fn subscribe(&self, topic: &str) -> Result<Subscription> {
+        Ok(self.add_subscriber(topic, None).boxed())
+    }
+
+    async fn subscribe_balanced(&self, topic: &str, group: &str) -> Result<AckedSubscription> {
+        let subscription = self.add_subscriber(topic, Some(group.to_string()));
+        Ok(subscription.map(|message| (message, Ack::none())).boxed())
+    }
+
+    fn has_built_in_balancer(&self) -> bool {
+        true
+    }
+
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> Result<()> {
+        if self.link.is_down() {
+            return Ok(());
+        }
+
+        let message = Bytes::from(message);
+
+        let mut bus = bus().lock().unwrap();
+        let subscribers = match bus.topics.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return Ok(()),
+        };
+
+        // drop subscribers whose stream has been dropped
+        subscribers.retain(|subscriber| !subscriber.tx.is_closed());
+
+        let mut groups: HashMap<&str, Vec<&Subscriber>> = HashMap::new();
+
+        for subscriber in subscribers
+            .iter()
+            .filter(|subscriber| !subscriber.link.is_down())
+        {
+            match &subscriber.group {
+                Some(group) => groups.entry(group).or_default().push(subscriber),
+                None => {
+                    let _ = subscriber.tx.unbounded_send(message.clone());
+                }
+            }
+        }
+
+        for members in groups.values() {
+            if let Some(subscriber) = members.choose(&mut rand::thread_rng()) {
+                let _ = subscriber.tx.unbounded_send(message.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn connection_events(&self) -> Option<ConnectionEvents> {
+        let events = self.connection_events.lock().unwrap().take()?;
+        Some(events.boxed())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        let mut bus = bus().lock().unwrap();
+
+        for subscribers in bus.topics.values_mut() {
+            subscribers.retain(|subscriber| subscriber.conn != self.id);
+        }
+        bus.topics.retain(|_, subscribers| !subscribers.is_empty());
+
+        Ok(())
+    }
+}
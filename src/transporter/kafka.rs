@@ -0,0 +1,250 @@
+use async_trait::async_trait;
+use futures::StreamExt as _;
+use log::{debug, warn};
+use rdkafka::{
+    admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+    client::DefaultClientContext,
+    consumer::{Consumer as _, StreamConsumer},
+    error::KafkaError,
+    producer::{FutureProducer, FutureRecord, Producer as _},
+    topic_partition_list::{Offset, TopicPartitionList},
+    types::RDKafkaErrorCode,
+    ClientConfig, Message as _,
+};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+
+use super::{Subscribers, Subscription, Transporter};
+use crate::config::{self, Config, KafkaOptions};
+
+type Result<T> = std::result::Result<T, self::Error>;
+
+/// How long to wait for the brokers when connecting and publishing
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("Unable to connect to Kafka: {0}")]
+    UnableToConnect(KafkaError),
+
+    #[error("Unable to create topic ({0}): {1}")]
+    UnableToCreateTopic(String, String),
+
+    #[error("Unable to subscribe to topic ({0}): {1}")]
+    UnableToSubscribe(String, KafkaError),
+
+    #[error("Unable to subscribe to topic ({0}): it has no partitions")]
+    NoPartitions(String),
+
+    #[error("Unable to publish to topic ({0}): {1}")]
+    PublishFailed(String, KafkaError),
+}
+
+/// One producer for all topics and one consumer reading all the topics of the node.
+/// Topics are created when subscribing, with the partitions and replication defaults of the brokers.
+///
+/// Each node has its own consumer group and reads every partition of its topics, so the partitions
+/// are assigned to the consumer instead of subscribing: adding a topic does not rebalance the group,
+/// which would stop the consumer and skip the packets sent meanwhile.
+pub(crate) struct Conn {
+    producer: FutureProducer,
+    consumer: Arc<StreamConsumer>,
+    admin: AdminClient<DefaultClientContext>,
+    topic_prefix: String,
+
+    subscribers: Arc<Subscribers>,
+    listener: JoinHandle<()>,
+}
+
+impl Conn {
+    pub(crate) async fn new(options: &KafkaOptions, node_id: &str) -> Result<Conn> {
+        let group_id = options.group_id.as_deref().unwrap_or(node_id);
+
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", &options.brokers)
+            .set("client.id", node_id);
+
+        let producer: FutureProducer = client_config.create().map_err(Error::UnableToConnect)?;
+
+        let admin: AdminClient<DefaultClientContext> =
+            client_config.create().map_err(Error::UnableToConnect)?;
+
+        let consumer: Arc<StreamConsumer> = Arc::new(
+            client_config
+                .clone()
+                .set("group.id", group_id)
+                .set("auto.offset.reset", "latest")
+                .create()
+                .map_err(Error::UnableToConnect)?,
+        );
+
+        // clients connect lazily, fetch the metadata so connection errors are returned
+        let client = producer.clone();
+        tokio::task::spawn_blocking(move || client.client().fetch_metadata(None, TIMEOUT))
+            .await
+            .expect("fetching metadata does not panic")
+            .map_err(Error::UnableToConnect)?;
+
+        let subscribers = Arc::new(Subscribers::default());
+
+        let listener = tokio::spawn(listen(
+            Arc::clone(&consumer),
+            options.topic_prefix.clone(),
+            Arc::clone(&subscribers),
+        ));
+
+        Ok(Conn {
+            producer,
+            consumer,
+            admin,
+            topic_prefix: options.topic_prefix.clone(),
+            subscribers,
+            listener,
+        })
+    }
+
+    fn topic_name(&self, topic: &str) -> String {
+        format!("{}{}", self.topic_prefix, topic)
+    }
+
+    async fn create_topic(&self, topic: &str) -> Result<()> {
+        let new_topic = NewTopic::new(topic, -1, TopicReplication::Fixed(-1));
+
+        let results = self
+            .admin
+            .create_topics(&[new_topic], &AdminOptions::new())
+            .await
+            .map_err(|e| Error::UnableToCreateTopic(topic.to_string(), e.to_string()))?;
+
+        for result in results {
+            match result {
+                Ok(_) | Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => {}
+                Err((topic, code)) => {
+                    return Err(Error::UnableToCreateTopic(topic, code.to_string()))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Partitions of the topic, the brokers can take a moment to report the ones of a new topic
+    async fn partitions(&self, topic: &str) -> Result<Vec<i32>> {
+        for _ in 0..10 {
+            let consumer = Arc::clone(&self.consumer);
+            let topic_name = topic.to_string();
+
+            let metadata = tokio::task::spawn_blocking(move || {
+                consumer.fetch_metadata(Some(&topic_name), TIMEOUT)
+            })
+            .await
+            .expect("fetching metadata does not panic")
+            .map_err(|e| Error::UnableToSubscribe(topic.to_string(), e))?;
+
+            let partitions: Vec<i32> = metadata
+                .topics()
+                .iter()
+                .filter(|metadata| metadata.name() == topic)
+                .flat_map(|metadata| metadata.partitions())
+                .map(|partition| partition.id())
+                .collect();
+
+            if !partitions.is_empty() {
+                return Ok(partitions);
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Err(Error::NoPartitions(topic.to_string()))
+    }
+}
+
+async fn listen(
+    consumer: Arc<StreamConsumer>,
+    topic_prefix: String,
+    subscribers: Arc<Subscribers>,
+) {
+    let mut messages = consumer.stream();
+
+    while let Some(message) = messages.next().await {
+        match message {
+            Ok(message) => {
+                let topic = message.topic();
+                let topic = topic.strip_prefix(topic_prefix.as_str()).unwrap_or(topic);
+                let payload = message.payload().unwrap_or_default().to_vec();
+
+                subscribers.publish(topic, payload.into())
+            }
+
+            // the consumer reconnects by itself
+            Err(err) => warn!("Kafka consumer error: {}", err),
+        }
+    }
+}
+
+#[async_trait]
+impl Transporter for Conn {
+    async fn connect(config: &Config) -> super::Result<Self> {
+        match &config.transporter {
+            config::Transporter::Kafka(options) => Ok(Conn::new(options, &config.node_id).await?),
+            _ => unreachable!("Kafka transporter used without a Kafka config"),
+        }
+    }
+
+    async fn subscribe(&self, topic: &str) -> super::Result<Subscription> {
+        let subscription = self.subscribers.subscribe(topic);
+        let topic = self.topic_name(topic);
+
+        self.create_topic(&topic).await?;
+
+        let assigned = self
+            .consumer
+            .assignment()
+            .map_err(|e| Error::UnableToSubscribe(topic.clone(), e))?;
+
+        // read from the committed offset, or from the end of the partition the first time
+        let mut assignment = TopicPartitionList::new();
+        for partition in self.partitions(&topic).await? {
+            if assigned.find_partition(&topic, partition).is_some() {
+                continue;
+            }
+
+            assignment
+                .add_partition_offset(&topic, partition, Offset::Stored)
+                .map_err(|e| Error::UnableToSubscribe(topic.clone(), e))?;
+        }
+
+        // added to the partitions already assigned
+        self.consumer
+            .incremental_assign(&assignment)
+            .map_err(|e| Error::UnableToSubscribe(topic.clone(), e))?;
+
+        debug!("Subscribed to Kafka topic: {}", topic);
+        Ok(subscription)
+    }
+
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> super::Result<()> {
+        let topic = self.topic_name(topic);
+        let record = FutureRecord::<(), _>::to(&topic).payload(&message);
+
+        self.producer
+            .send(record, TIMEOUT)
+            .await
+            .map_err(|(e, _)| Error::PublishFailed(topic.clone(), e))?;
+
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> super::Result<()> {
+        self.listener.abort();
+        if let Err(err) = self.consumer.unassign() {
+            warn!("Unable to unassign the Kafka partitions: {}", err);
+        }
+        self.subscribers.clear();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use async_trait::async_trait;
+use log::{debug, warn};
+use rumqttc::{AsyncClient, ClientError, ConnectionError, Event, EventLoop, Outgoing, Packet, QoS};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+use super::{Subscribers, Subscription, Transporter};
+use crate::config::{self, Config, MqttOptions};
+
+type Result<T> = std::result::Result<T, self::Error>;
+
+/// Max size of incoming and outgoing packets, INFO packets get big with many services
+const MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("Invalid MQTT url: {0}")]
+    InvalidUrl(#[from] rumqttc::OptionError),
+
+    #[error("Invalid MQTT QoS {0}, must be 0, 1 or 2")]
+    InvalidQos(u8),
+
+    #[error("Unable to connect to MQTT: {0}")]
+    UnableToConnect(#[from] ConnectionError),
+
+    #[error("Unable to subscribe to topic ({0}): {1}")]
+    UnableToSubscribe(String, ClientError),
+
+    #[error("Unable to publish to topic ({0}): {1}")]
+    PublishFailed(String, ClientError),
+
+    #[error("Unable to disconnect from MQTT: {0}")]
+    DisconnectFailed(ClientError),
+}
+
+pub(crate) struct Conn {
+    client: AsyncClient,
+    qos: QoS,
+    topic_separator: String,
+
+    subscribers: Arc<Subscribers>,
+    /// subscribed topics, subscribed again after reconnecting
+    topics: Arc<Mutex<Vec<String>>>,
+}
+
+impl Conn {
+    pub(crate) async fn new(options: &MqttOptions, node_id: &str) -> Result<Conn> {
+        let qos = rumqttc::qos(options.qos).map_err(|_| Error::InvalidQos(options.qos))?;
+
+        let url = if options.url.contains("client_id=") {
+            options.url.clone()
+        } else {
+            let separator = if options.url.contains('?') { '&' } else { '?' };
+            format!("{}{}client_id={}", options.url, separator, node_id)
+        };
+
+        let mut mqtt_options = rumqttc::MqttOptions::parse_url(url)?;
+        mqtt_options.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+
+        let (client, mut event_loop) = AsyncClient::new(mqtt_options, 100);
+
+        // wait until connected so connection errors are returned
+        loop {
+            if let Event::Incoming(Packet::ConnAck(_)) = event_loop.poll().await? {
+                break;
+            }
+        }
+
+        let subscribers = Arc::new(Subscribers::default());
+        let topics = Arc::new(Mutex::new(vec![]));
+
+        tokio::spawn(listen(
+            event_loop,
+            client.clone(),
+            qos,
+            Arc::clone(&subscribers),
+            Arc::clone(&topics),
+        ));
+
+        Ok(Conn {
+            client,
+            qos,
+            topic_separator: options.topic_separator.clone(),
+            subscribers,
+            topics,
+        })
+    }
+
+    fn topic_name(&self, topic: &str) -> String {
+        topic_name(topic, &self.topic_separator)
+    }
+}
+
+/// `MOL.REQ.node-1` becomes `MOL/REQ/node-1`, dots in the node id are kept
+fn topic_name(topic: &str, separator: &str) -> String {
+    topic.splitn(3, '.').collect::<Vec<_>>().join(separator)
+}
+
+async fn listen(
+    mut event_loop: EventLoop,
+    client: AsyncClient,
+    qos: QoS,
+    subscribers: Arc<Subscribers>,
+    topics: Arc<Mutex<Vec<String>>>,
+) {
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                subscribers.publish(&publish.topic, publish.payload)
+            }
+
+            // reconnected without a session, the broker forgot the subscriptions
+            Ok(Event::Incoming(Packet::ConnAck(ack))) if !ack.session_present => {
+                debug!("Reconnected to MQTT, subscribing again");
+
+                for topic in topics.lock().unwrap().iter() {
+                    if let Err(err) = client.try_subscribe(topic, qos) {
+                        warn!("Unable to subscribe again to topic ({}): {}", topic, err)
+                    }
+                }
+            }
+
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+
+            Ok(_) => {}
+
+            // polling again reconnects
+            Err(err) => {
+                warn!("MQTT connection error: {}", err);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transporter for Conn {
+    async fn connect(config: &Config) -> super::Result<Self> {
+        match &config.transporter {
+            config::Transporter::Mqtt(options) => Ok(Conn::new(options, &config.node_id).await?),
+            _ => unreachable!("MQTT transporter used without a MQTT config"),
+        }
+    }
+
+    async fn subscribe(&self, topic: &str) -> super::Result<Subscription> {
+        let topic = self.topic_name(topic);
+        let subscription = self.subscribers.subscribe(&topic);
+
+        self.client
+            .subscribe(&topic, self.qos)
+            .await
+            .map_err(|e| Error::UnableToSubscribe(topic.clone(), e))?;
+
+        self.topics.lock().unwrap().push(topic);
+        Ok(subscription)
+    }
+
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> super::Result<()> {
+        let topic = self.topic_name(topic);
+
+        self.client
+            .publish(&topic, self.qos, false, message)
+            .await
+            .map_err(|e| Error::PublishFailed(topic, e))?;
+
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> super::Result<()> {
+        // the event loop stops once the DISCONNECT is sent
+        self.client
+            .disconnect()
+            .await
+            .map_err(Error::DisconnectFailed)?;
+
+        self.subscribers.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::topic_name;
+
+    #[test]
+    fn topic_name_keeps_dots_after_the_command() {
+        assert_eq!(topic_name("MOL.REQB.math.add", "/"), "MOL/REQB/math.add");
+        assert_eq!(topic_name("MOL.REQ.node.1", "/"), "MOL/REQ/node.1");
+        assert_eq!(
+            topic_name("MOL-dev.EVENTB.users.user.created", "/"),
+            "MOL-dev/EVENTB/users.user.created"
+        );
+        assert_eq!(topic_name("MOL.DISCOVER", "/"), "MOL/DISCOVER");
+        assert_eq!(topic_name("MOL.INFO.node-1", "."), "MOL.INFO.node-1");
+    }
+}
@@ -0,0 +1,500 @@
+mod outgoing;
+
+use async_nats::{
+    client::{FlushError, PublishErrorKind},
+    connection::State,
+    jetstream::{
+        self,
+        consumer::{pull, DeliverPolicy, StreamError},
+        context::{self as jetstream_context, CreateStreamError, PublishError},
+        stream::{self, ConsumerError, RetentionPolicy},
+    },
+    ConnectErrorKind, ConnectOptions, Event, Subject, SubscribeError,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{channel::mpsc, StreamExt as _};
+use log::{debug, error, warn};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+use self::outgoing::{Outgoing, Publisher};
+use super::{Ack, AckedSubscription, ConnectionEvent, ConnectionEvents, Subscription, Transporter};
+use crate::config::{self, Config, JetStreamOptions, NatsOptions};
+
+type Result<T> = std::result::Result<T, self::Error>;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("Unable to connect to NATS: {0}")]
+    UnableToConnect(#[from] async_nats::error::Error<ConnectErrorKind>),
+
+    #[error("Unable to publish to channel ({0}): {1}")]
+    PublishFailed(String, async_nats::PublishError),
+
+    #[error("Unable to publish to channel ({0}): disconnected and the outgoing buffer is full")]
+    OutgoingBufferFull(String),
+
+    #[error("Invalid NATS options: `user` and `password` must be set together")]
+    IncompleteUserAndPassword,
+
+    #[error("Unable to read NATS credentials file ({0}): {1}")]
+    UnableToReadCredentials(PathBuf, std::io::Error),
+
+    #[error("Unable to subscribe to channel ({0}): {1}")]
+    UnableToSubscribe(String, SubscribeError),
+
+    #[error("Unable to flush messages before disconnecting: {0}")]
+    FlushFailed(#[from] FlushError),
+
+    #[error("Unable to create JetStream stream ({0}): {1}")]
+    UnableToCreateStream(String, CreateStreamError),
+
+    #[error("Unable to create JetStream consumer ({0}): {1}")]
+    UnableToCreateConsumer(String, ConsumerError),
+
+    #[error("Unable to consume JetStream messages ({0}): {1}")]
+    UnableToConsume(String, StreamError),
+
+    #[error("Unable to publish to JetStream ({0}): {1}")]
+    JetStreamPublishFailed(String, PublishError),
+}
+
+impl Error {
+    /// The connection went away while publishing, trying again once reconnected can work
+    fn lost_connection(&self) -> bool {
+        match self {
+            Error::PublishFailed(_, error) => error.kind() == PublishErrorKind::Send,
+            Error::JetStreamPublishFailed(_, error) => {
+                error.kind() == jetstream_context::PublishErrorKind::BrokenPipe
+            }
+            _ => false,
+        }
+    }
+}
+
+pub(crate) struct Conn {
+    client: Client,
+    outgoing: Arc<Outgoing>,
+
+    /// taken by the first call to [Transporter::connection_events()]
+    connection_events: Mutex<Option<mpsc::UnboundedReceiver<ConnectionEvent>>>,
+}
+
+/// The NATS client, publishing the events stored by JetStream to the stream
+#[derive(Clone)]
+struct Client {
+    conn: async_nats::Client,
+    jetstream: Option<Arc<JetStream>>,
+}
+
+/// Stream storing the EVENTB packets, read by a durable consumer per group
+struct JetStream {
+    context: jetstream::Context,
+    stream: stream::Stream,
+    /// `MOL.`, removed from the consumer names
+    mol_prefix: String,
+    /// `MOL.EVENTB.`
+    subject_prefix: String,
+    consumer_inactive_threshold: Duration,
+}
+
+impl Conn {
+    pub(crate) async fn new(options: &NatsOptions, config: &Config) -> Result<Conn> {
+        let servers: Vec<&str> = options.url.split(',').map(str::trim).collect();
+        let (events_tx, mut events) = mpsc::unbounded();
+
+        let conn = connect_options(options, config)
+            .await?
+            .event_callback(move |event| {
+                let _ = events_tx.unbounded_send(event);
+                async {}
+            })
+            .connect(servers)
+            .await
+            .map_err(Error::UnableToConnect)?;
+
+        let jetstream = match &options.jetstream {
+            Some(jetstream_options) => Some(Arc::new(
+                JetStream::new(conn.clone(), jetstream_options, config).await?,
+            )),
+            None => None,
+        };
+        let client = Client { conn, jetstream };
+
+        let outgoing = Arc::new(Outgoing::new(
+            config.transit.max_queue_size as usize,
+            options.max_publish_attempts,
+            options.publish_retry_delay,
+        ));
+
+        let (connection_events_tx, connection_events) = mpsc::unbounded();
+
+        let outgoing_clone = Arc::clone(&outgoing);
+        let client_clone = client.clone();
+        tokio::spawn(async move {
+            // `Connected` is also sent for the first connection, only report reconnections
+            let mut disconnected = false;
+
+            while let Some(event) = events.next().await {
+                match event {
+                    Event::Connected => {
+                        outgoing_clone.flush(&client_clone).await;
+
+                        if disconnected {
+                            disconnected = false;
+                            let _ =
+                                connection_events_tx.unbounded_send(ConnectionEvent::Reconnected);
+                        }
+                    }
+                    Event::Disconnected => {
+                        warn!("Disconnected from NATS");
+
+                        disconnected = true;
+                        let _ = connection_events_tx.unbounded_send(ConnectionEvent::Disconnected);
+                    }
+                    event => debug!("NATS connection event: {}", event),
+                }
+            }
+        });
+
+        Ok(Conn {
+            client,
+            outgoing,
+            connection_events: Mutex::new(Some(connection_events)),
+        })
+    }
+}
+
+impl Client {
+    /// The JetStream stream if the topic carries events that should be stored in it
+    fn event_stream(&self, topic: &str) -> Option<&JetStream> {
+        self.jetstream
+            .as_deref()
+            .filter(|jetstream| topic.starts_with(jetstream.subject_prefix.as_str()))
+    }
+}
+
+#[async_trait]
+impl Publisher for Client {
+    fn is_connected(&self) -> bool {
+        self.conn.connection_state() == State::Connected
+    }
+
+    async fn publish(&self, channel: &str, message: Bytes) -> Result<()> {
+        if let Some(jetstream) = self.event_stream(channel) {
+            return jetstream.publish(channel, message).await;
+        }
+
+        self.conn
+            .publish(Subject::from(channel), message)
+            .await
+            .map_err(|e| Error::PublishFailed(channel.to_string(), e))
+    }
+}
+
+async fn connect_options(options: &NatsOptions, config: &Config) -> Result<ConnectOptions> {
+    let name = options.name.as_ref().unwrap_or(&config.node_id);
+    let mut connect_options = ConnectOptions::new()
+        .name(name)
+        .max_reconnects(options.max_reconnects);
+
+    match (&options.user, &options.password) {
+        (Some(user), Some(password)) => {
+            connect_options = connect_options.user_and_password(user.clone(), password.clone());
+        }
+        (None, None) => {}
+        // connecting without auth would only fail later, with a less helpful error
+        _ => return Err(Error::IncompleteUserAndPassword),
+    }
+
+    if let Some(token) = &options.token {
+        connect_options = connect_options.token(token.clone());
+    }
+
+    if let Some(nkey) = &options.nkey {
+        connect_options = connect_options.nkey(nkey.clone());
+    }
+
+    if let Some(credentials_file) = &options.credentials_file {
+        connect_options = connect_options
+            .credentials_file(credentials_file)
+            .await
+            .map_err(|e| Error::UnableToReadCredentials(credentials_file.clone(), e))?;
+    }
+
+    if let Some(tls_ca_file) = &options.tls_ca_file {
+        connect_options = connect_options.add_root_certificates(tls_ca_file.clone());
+    }
+
+    if let (Some(tls_cert_file), Some(tls_key_file)) =
+        (&options.tls_cert_file, &options.tls_key_file)
+    {
+        connect_options =
+            connect_options.add_client_certificate(tls_cert_file.clone(), tls_key_file.clone());
+    }
+
+    let require_tls = options.require_tls
+        || options.tls_ca_file.is_some()
+        || options.tls_cert_file.is_some()
+        || options.tls_key_file.is_some();
+
+    if require_tls {
+        connect_options = connect_options.require_tls(true);
+    }
+
+    if let Some(reconnect_delay) = options.reconnect_delay {
+        connect_options = connect_options.reconnect_delay_callback(move |_| reconnect_delay);
+    }
+
+    Ok(connect_options)
+}
+
+impl JetStream {
+    async fn new(
+        client: async_nats::Client,
+        options: &JetStreamOptions,
+        config: &Config,
+    ) -> Result<JetStream> {
+        let mol = config::mol(config);
+        let name = options
+            .stream_name
+            .clone()
+            .unwrap_or_else(|| format!("{}_EVENTS", mol));
+
+        let subject_prefix = format!("{}.EVENTB.", mol);
+
+        let context = jetstream::new(client);
+        let stream = context
+            .get_or_create_stream(stream::Config {
+                name: name.clone(),
+                subjects: vec![format!("{}>", subject_prefix)],
+                // events are removed once every consumer acknowledged them
+                retention: RetentionPolicy::Interest,
+                max_age: options.max_age,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| Error::UnableToCreateStream(name, e))?;
+
+        Ok(JetStream {
+            context,
+            stream,
+            mol_prefix: format!("{}.", mol),
+            subject_prefix,
+            consumer_inactive_threshold: options.consumer_inactive_threshold,
+        })
+    }
+
+    /// Read the events of the topic through a durable consumer shared by the nodes of the group,
+    /// each event is acknowledged once handled.
+    ///
+    /// The consumer is named after the group and the event, ex: `EVENTB_users_user_created`
+    /// for the `user.created` events balanced between the `users` service instances.
+    async fn subscribe(&self, topic: &str) -> Result<AckedSubscription> {
+        let durable_name = topic
+            .strip_prefix(self.mol_prefix.as_str())
+            .unwrap_or(topic)
+            .replace(['.', '*', '>', ' ', '\t'], "_");
+
+        let consumer = self
+            .stream
+            .get_or_create_consumer(
+                &durable_name,
+                pull::Config {
+                    durable_name: Some(durable_name.clone()),
+                    filter_subject: topic.to_string(),
+                    deliver_policy: DeliverPolicy::New,
+                    inactive_threshold: self.consumer_inactive_threshold,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| Error::UnableToCreateConsumer(durable_name.clone(), e))?;
+
+        let messages = consumer
+            .messages()
+            .await
+            .map_err(|e| Error::UnableToConsume(durable_name.clone(), e))?;
+
+        debug!("Consuming JetStream events with consumer: {}", durable_name);
+
+        let subscription = messages.filter_map(|message| async move {
+            let message = match message {
+                Ok(message) => message,
+                Err(err) => {
+                    error!("Unable to receive JetStream message: {}", err);
+                    return None;
+                }
+            };
+
+            let payload = message.message.payload.clone();
+            let ack = Ack::new(async move {
+                if let Err(err) = message.ack().await {
+                    error!("Unable to acknowledge JetStream message: {}", err);
+                }
+            });
+
+            Some((payload, ack))
+        });
+
+        Ok(subscription.boxed())
+    }
+
+    /// Publish and wait until the event is stored
+    async fn publish(&self, topic: &str, message: Bytes) -> Result<()> {
+        self.context
+            .publish(topic.to_string(), message)
+            .await
+            .map_err(|e| Error::JetStreamPublishFailed(topic.to_string(), e))?
+            .await
+            .map_err(|e| Error::JetStreamPublishFailed(topic.to_string(), e))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transporter for Conn {
+    async fn connect(config: &Config) -> super::Result<Self> {
+        match &config.transporter {
+            config::Transporter::Nats(options) => Ok(Conn::new(options, config).await?),
+            _ => unreachable!("NATS transporter used without a NATS config"),
+        }
+    }
+
+    async fn subscribe(&self, topic: &str) -> super::Result<Subscription> {
+        let channel = Subject::from(topic);
+
+        let subscriber = self
+            .client
+            .conn
+            .subscribe(channel.clone())
+            .await
+            .map_err(|e| Error::UnableToSubscribe(channel.to_string(), e))?;
+
+        Ok(subscriber.map(|msg| msg.payload).boxed())
+    }
+
+    async fn subscribe_balanced(
+        &self,
+        topic: &str,
+        group: &str,
+    ) -> super::Result<AckedSubscription> {
+        // durable consumers are already shared by the nodes
+        if let Some(jetstream) = self.client.event_stream(topic) {
+            return Ok(jetstream.subscribe(topic).await?);
+        }
+
+        let channel = Subject::from(topic);
+
+        let subscriber = self
+            .client
+            .conn
+            .queue_subscribe(channel.clone(), group.to_string())
+            .await
+            .map_err(|e| Error::UnableToSubscribe(channel.to_string(), e))?;
+
+        Ok(subscriber.map(|msg| (msg.payload, Ack::none())).boxed())
+    }
+
+    fn has_built_in_balancer(&self) -> bool {
+        true
+    }
+
+    fn stores_events(&self) -> bool {
+        self.client.jetstream.is_some()
+    }
+
+    /// Retried and buffered while disconnected, also the events stored by JetStream
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> super::Result<()> {
+        let message = Bytes::from(message);
+        Ok(self.outgoing.send(&self.client, topic, message).await?)
+    }
+
+    /// The client subscribes again by itself after reconnecting
+    fn connection_events(&self) -> Option<ConnectionEvents> {
+        let events = self.connection_events.lock().unwrap().take()?;
+        Some(events.boxed())
+    }
+
+    async fn disconnect(&self) -> super::Result<()> {
+        self.client.conn.flush().await.map_err(Error::FlushFailed)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigBuilder, NatsOptionsBuilder};
+
+    async fn debug_options(options: NatsOptionsBuilder) -> Result<String> {
+        let options = options.build();
+        let config = ConfigBuilder::default().node_id("node-1").build();
+
+        Ok(format!("{:?}", connect_options(&options, &config).await?))
+    }
+
+    #[tokio::test]
+    async fn connect_options_default_to_the_node_id() {
+        let options = debug_options(NatsOptionsBuilder::default()).await.unwrap();
+
+        assert!(options.contains(r#""name": Some("node-1")"#), "{}", options);
+        assert!(options.contains(r#""max_reconnects": None"#), "{}", options);
+        assert!(options.contains(r#""tls_required": false"#), "{}", options);
+    }
+
+    #[tokio::test]
+    async fn connect_options_follow_the_nats_options() {
+        let options = NatsOptionsBuilder::default()
+            .name("api")
+            .user("john")
+            .password("secret")
+            .max_reconnects(3_usize)
+            .tls_ca_file(PathBuf::from("ca.pem"))
+            .tls_cert_file(PathBuf::from("client.pem"))
+            .tls_key_file(PathBuf::from("client-key.pem"));
+        let options = debug_options(options).await.unwrap();
+
+        assert!(options.contains(r#""name": Some("api")"#), "{}", options);
+        assert!(
+            options.contains(r#""max_reconnects": Some(3)"#),
+            "{}",
+            options
+        );
+        // implied by the certificates
+        assert!(options.contains(r#""tls_required": true"#), "{}", options);
+        assert!(
+            options.contains(r#""certificates": ["ca.pem"]"#),
+            "{}",
+            options
+        );
+        assert!(
+            options.contains(r#""client_cert": Some("client.pem")"#),
+            "{}",
+            options
+        );
+        assert!(
+            options.contains(r#""client_key": Some("client-key.pem")"#),
+            "{}",
+            options
+        );
+    }
+
+    #[tokio::test]
+    async fn user_and_password_go_together() {
+        let user_only = debug_options(NatsOptionsBuilder::default().user("john")).await;
+        assert!(matches!(user_only, Err(Error::IncompleteUserAndPassword)));
+
+        let password_only = debug_options(NatsOptionsBuilder::default().password("secret")).await;
+        assert!(matches!(
+            password_only,
+            Err(Error::IncompleteUserAndPassword)
+        ));
+    }
+}
@@ -0,0 +1,256 @@
+//! Retries of the failed publishes and buffering of the packets published while disconnected,
+//! for core NATS and the events stored by JetStream alike.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::{debug, error, warn};
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use super::{Error, Result};
+
+/// Publishes a packet once, the NATS client outside of the tests
+#[async_trait]
+pub(super) trait Publisher: Send + Sync {
+    fn is_connected(&self) -> bool;
+
+    async fn publish(&self, channel: &str, message: Bytes) -> Result<()>;
+}
+
+/// Packets published while disconnected, sent once connected again
+pub(super) struct Outgoing {
+    packets: Mutex<VecDeque<(String, Bytes)>>,
+    max_size: usize,
+    max_attempts: u32,
+    retry_delay: Duration,
+}
+
+impl Outgoing {
+    pub(super) fn new(max_size: usize, max_attempts: u32, retry_delay: Duration) -> Outgoing {
+        Outgoing {
+            packets: Mutex::new(VecDeque::new()),
+            max_size,
+            max_attempts,
+            retry_delay,
+        }
+    }
+
+    /// Publish with retries, buffered while disconnected so publishing does not block until reconnected
+    pub(super) async fn send(
+        &self,
+        publisher: &impl Publisher,
+        channel: &str,
+        message: Bytes,
+    ) -> Result<()> {
+        if self.push_if_waiting(publisher, channel, &message)? {
+            return Ok(());
+        }
+
+        let mut attempts = 1;
+        let mut delay = self.retry_delay;
+
+        loop {
+            let error = match publisher.publish(channel, message.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+
+            // only the connection going away can be fixed by trying again
+            if attempts >= self.max_attempts || !error.lost_connection() {
+                return Err(error);
+            }
+
+            warn!(
+                "Failed to send message to {}, failed {} times: {}",
+                channel, attempts, error
+            );
+
+            tokio::time::sleep(delay).await;
+            attempts += 1;
+            delay *= 2;
+        }
+    }
+
+    /// Buffer the packet while disconnected or while buffered packets are waiting to be sent,
+    /// so packets are sent in order. `false` when the packet can be published right away.
+    fn push_if_waiting(
+        &self,
+        publisher: &impl Publisher,
+        channel: &str,
+        message: &Bytes,
+    ) -> Result<bool> {
+        // checked while locked, so the buffer can't be emptied between the check and the push
+        let mut packets = self.packets.lock().unwrap();
+
+        if publisher.is_connected() && packets.is_empty() {
+            return Ok(false);
+        }
+
+        if packets.len() >= self.max_size {
+            return Err(Error::OutgoingBufferFull(channel.to_string()));
+        }
+
+        packets.push_back((channel.to_string(), message.clone()));
+        Ok(true)
+    }
+
+    /// Send the buffered packets until none is left, including the ones buffered meanwhile
+    pub(super) async fn flush(&self, publisher: &impl Publisher) {
+        let buffered = self.packets.lock().unwrap().len();
+        if buffered > 0 {
+            debug!("Sending {} packets buffered while disconnected", buffered);
+        }
+
+        loop {
+            // stays in the buffer until sent, so new packets keep waiting behind it
+            let (channel, message) = match self.packets.lock().unwrap().front() {
+                Some(packet) => packet.clone(),
+                None => return,
+            };
+
+            match publisher.publish(&channel, message).await {
+                Ok(()) => {}
+                Err(err) if err.lost_connection() => {
+                    error!(
+                        "Unable to send buffered message to {}, retrying once reconnected: {}",
+                        channel, err
+                    );
+                    return;
+                }
+                Err(err) => error!("Unable to send buffered message to {}: {}", channel, err),
+            }
+
+            self.packets.lock().unwrap().pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_nats::client::PublishErrorKind;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    /// Fails the given number of publishes as if the connection went away
+    #[derive(Default)]
+    struct FakePublisher {
+        connected: AtomicBool,
+        failures: AtomicU32,
+        attempts: AtomicU32,
+        published: Mutex<Vec<String>>,
+    }
+
+    impl FakePublisher {
+        fn connected(failures: u32) -> FakePublisher {
+            FakePublisher {
+                connected: AtomicBool::new(true),
+                failures: AtomicU32::new(failures),
+                ..Default::default()
+            }
+        }
+
+        fn published(&self) -> Vec<String> {
+            self.published.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Publisher for FakePublisher {
+        fn is_connected(&self) -> bool {
+            self.connected.load(Ordering::SeqCst)
+        }
+
+        async fn publish(&self, channel: &str, _message: Bytes) -> Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+
+            let failures = self.failures.load(Ordering::SeqCst);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::SeqCst);
+                let error = PublishErrorKind::Send.into();
+                return Err(Error::PublishFailed(channel.to_string(), error));
+            }
+
+            self.published.lock().unwrap().push(channel.to_string());
+            Ok(())
+        }
+    }
+
+    fn outgoing(max_size: usize) -> Outgoing {
+        Outgoing::new(max_size, 3, Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn send_retries_up_to_the_max_attempts() {
+        let outgoing = outgoing(10);
+
+        let publisher = FakePublisher::connected(2);
+        outgoing
+            .send(&publisher, "MOL.REQ.node-1", Bytes::new())
+            .await
+            .unwrap();
+        assert_eq!(publisher.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(publisher.published(), vec!["MOL.REQ.node-1"]);
+
+        let publisher = FakePublisher::connected(3);
+        let result = outgoing
+            .send(&publisher, "MOL.REQ.node-1", Bytes::new())
+            .await;
+        assert!(matches!(result, Err(Error::PublishFailed(..))));
+        assert_eq!(publisher.attempts.load(Ordering::SeqCst), 3);
+        assert!(publisher.published().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_fails_once_the_buffer_is_full() {
+        let outgoing = outgoing(2);
+        let publisher = FakePublisher::default();
+
+        outgoing
+            .send(&publisher, "MOL.EVENT.a", Bytes::new())
+            .await
+            .unwrap();
+        outgoing
+            .send(&publisher, "MOL.EVENT.b", Bytes::new())
+            .await
+            .unwrap();
+
+        let result = outgoing.send(&publisher, "MOL.EVENT.c", Bytes::new()).await;
+        assert!(
+            matches!(result, Err(Error::OutgoingBufferFull(channel)) if channel == "MOL.EVENT.c")
+        );
+        assert_eq!(publisher.attempts.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn flush_sends_in_order_once_reconnected() {
+        let outgoing = outgoing(10);
+        let publisher = FakePublisher::default();
+
+        outgoing
+            .send(&publisher, "MOL.EVENT.a", Bytes::new())
+            .await
+            .unwrap();
+        outgoing
+            .send(&publisher, "MOL.EVENT.b", Bytes::new())
+            .await
+            .unwrap();
+        assert!(publisher.published().is_empty());
+
+        // waits behind the buffered packets until they are flushed
+        publisher.connected.store(true, Ordering::SeqCst);
+        outgoing
+            .send(&publisher, "MOL.EVENT.c", Bytes::new())
+            .await
+            .unwrap();
+        assert!(publisher.published().is_empty());
+
+        outgoing.flush(&publisher).await;
+        outgoing
+            .send(&publisher, "MOL.EVENT.d", Bytes::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            publisher.published(),
+            vec!["MOL.EVENT.a", "MOL.EVENT.b", "MOL.EVENT.c", "MOL.EVENT.d"]
+        );
+    }
+}
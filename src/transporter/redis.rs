@@ -0,0 +1,113 @@
+use ::redis::{
+    aio::{MultiplexedConnection, PubSubSink},
+    AsyncCommands as _, Client, RedisError,
+};
+use async_trait::async_trait;
+use futures::StreamExt as _;
+use log::{debug, warn};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+
+use super::{Subscribers, Subscription, Transporter};
+use crate::config::{self, Config};
+
+type Result<T> = std::result::Result<T, self::Error>;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("Unable to connect to Redis: {0}")]
+    UnableToConnect(RedisError),
+
+    #[error("Unable to subscribe to channel ({0}): {1}")]
+    UnableToSubscribe(String, RedisError),
+
+    #[error("Unable to publish to channel ({0}): {1}")]
+    PublishFailed(String, RedisError),
+}
+
+/// Uses Redis pub/sub, one connection to publish and one to receive the messages of all channels.
+pub(crate) struct Conn {
+    publisher: MultiplexedConnection,
+    subscriber: PubSubSink,
+    subscribers: Arc<Subscribers>,
+    listener: JoinHandle<()>,
+}
+
+impl Conn {
+    pub(crate) async fn new(redis_address: &str) -> Result<Conn> {
+        let client = Client::open(redis_address).map_err(Error::UnableToConnect)?;
+
+        let publisher = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(Error::UnableToConnect)?;
+
+        let (subscriber, mut messages) = client
+            .get_async_pubsub()
+            .await
+            .map_err(Error::UnableToConnect)?
+            .split();
+
+        let subscribers = Arc::new(Subscribers::default());
+        let subscribers_clone = Arc::clone(&subscribers);
+
+        let listener = tokio::spawn(async move {
+            while let Some(msg) = messages.next().await {
+                subscribers_clone.publish(
+                    msg.get_channel_name(),
+                    msg.get_payload_bytes().to_vec().into(),
+                );
+            }
+
+            warn!("Redis subscriber connection closed");
+        });
+
+        Ok(Conn {
+            publisher,
+            subscriber,
+            subscribers,
+            listener,
+        })
+    }
+}
+
+#[async_trait]
+impl Transporter for Conn {
+    async fn connect(config: &Config) -> super::Result<Self> {
+        match &config.transporter {
+            config::Transporter::Redis(redis_address) => Ok(Conn::new(redis_address).await?),
+            _ => unreachable!("Redis transporter used without a Redis config"),
+        }
+    }
+
+    async fn subscribe(&self, topic: &str) -> super::Result<Subscription> {
+        let subscription = self.subscribers.subscribe(topic);
+
+        self.subscriber
+            .clone()
+            .subscribe(topic)
+            .await
+            .map_err(|e| Error::UnableToSubscribe(topic.to_string(), e))?;
+
+        debug!("Subscribed to Redis channel: {}", topic);
+        Ok(subscription)
+    }
+
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> super::Result<()> {
+        self.publisher
+            .clone()
+            .publish::<_, _, ()>(topic, message)
+            .await
+            .map_err(|e| Error::PublishFailed(topic.to_string(), e))?;
+
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> super::Result<()> {
+        self.listener.abort();
+        self.subscribers.clear();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,958 @@
+//! TCP transporter compatible with the moleculerjs `TCP` transporter.
+//!
+//! Nodes are found with UDP announcements (or the configured `urls`), after that
+//! they exchange their INFO and CPU usage with GOSSIP_REQ/GOSSIP_RES packets.
+//! EVENT, REQ, RES, PING and PONG packets are sent over a direct TCP connection to the target node.
+
+mod frame;
+mod gossip;
+mod udp;
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{channel::mpsc, StreamExt as _};
+use log::{debug, warn};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::{
+    io::{AsyncWriteExt as _, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::JoinHandle,
+};
+
+use self::{
+    frame::PacketType,
+    gossip::{HelloMessage, OnlineUpdate, RequestMessage, ResponseMessage},
+};
+use super::{Subscribers, Subscription, Transporter};
+use crate::{
+    channels::messages::{incoming, outgoing},
+    config::{self, Config, PacketKind, Serializer, TcpOptions},
+};
+
+type Result<T> = std::result::Result<T, self::Error>;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("Unable to listen for TCP connections: {0}")]
+    Listen(std::io::Error),
+
+    #[error("Unable to start UDP discovery: {0}")]
+    Udp(std::io::Error),
+
+    #[error("No address known for node '{0}'")]
+    UnknownNode(String),
+
+    #[error("Packet checksum does not match")]
+    InvalidChecksum,
+
+    #[error("Packet of {0} bytes is bigger than the max packet size")]
+    PacketTooLarge(usize),
+
+    #[error("Unknown packet type: {0}")]
+    UnknownPacketType(u8),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Serialize(#[from] config::SerializeError),
+
+    #[error(transparent)]
+    Deserialize(#[from] config::DeserializeError),
+}
+
+pub(crate) struct Conn {
+    inner: Arc<Inner>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+struct Inner {
+    node_id: String,
+    namespace: String,
+    prefix: String,
+    serializer: Serializer,
+    options: TcpOptions,
+
+    /// where other nodes can reach this node
+    host: String,
+    port: u16,
+
+    state: Mutex<State>,
+    subscribers: Subscribers,
+}
+
+#[derive(Default)]
+struct State {
+    local: LocalNode,
+    nodes: HashMap<String, Node>,
+    writers: HashMap<String, mpsc::UnboundedSender<Vec<u8>>>,
+}
+
+#[derive(Default)]
+struct LocalNode {
+    info: Option<Value>,
+    seq: u64,
+    cpu_seq: u64,
+    cpu: f32,
+}
+
+#[derive(Default)]
+struct Node {
+    info: Option<Value>,
+    seq: u64,
+    cpu_seq: u64,
+    cpu: f32,
+    address: Option<SocketAddr>,
+    online: bool,
+}
+
+impl Node {
+    fn instance_id(&self) -> Option<&Value> {
+        self.info.as_ref()?.get("instanceID")
+    }
+}
+
+#[async_trait]
+impl Transporter for Conn {
+    async fn connect(config: &Config) -> super::Result<Self> {
+        let options = match &config.transporter {
+            config::Transporter::Tcp(options) => options.clone(),
+            _ => unreachable!("TCP transporter used without a TCP config"),
+        };
+
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, options.port))
+            .await
+            .map_err(Error::Listen)?;
+        let port = listener.local_addr().map_err(Error::Listen)?.port();
+
+        let inner = Arc::new(Inner {
+            node_id: config.node_id.clone(),
+            namespace: config.namespace.clone(),
+            prefix: config::mol(config).into_owned(),
+            serializer: config.serializer.clone(),
+            host: config
+                .ip_list
+                .first()
+                .cloned()
+                .unwrap_or_else(|| config.hostname.clone()),
+            port,
+            options,
+            state: Mutex::new(State::default()),
+            subscribers: Subscribers::default(),
+        });
+
+        inner.add_urls().await;
+
+        let mut tasks = vec![
+            tokio::spawn(Arc::clone(&inner).accept(listener)),
+            tokio::spawn(Arc::clone(&inner).gossip()),
+        ];
+
+        if inner.options.udp_discovery {
+            let socket = Arc::new(udp::bind(&inner.options).map_err(Error::Udp)?);
+
+            tasks.push(tokio::spawn(
+                Arc::clone(&inner).udp_listen(Arc::clone(&socket)),
+            ));
+            tasks.push(tokio::spawn(Arc::clone(&inner).udp_announce(socket)));
+        }
+
+        Ok(Conn {
+            inner,
+            tasks: Mutex::new(tasks),
+        })
+    }
+
+    async fn subscribe(&self, topic: &str) -> super::Result<Subscription> {
+        Ok(self.inner.subscribers.subscribe(topic))
+    }
+
+    async fn publish(&self, topic: &str, message: Vec<u8>) -> super::Result<()> {
+        let inner = &self.inner;
+
+        let (command, target) = match topic
+            .strip_prefix(inner.prefix.as_str())
+            .and_then(|topic| topic.strip_prefix('.'))
+        {
+            Some(topic) => topic
+                .split_once('.')
+                .map_or((topic, None), |(command, target)| (command, Some(target))),
+            None => return Ok(()),
+        };
+
+        // packets sent to this node never leave the process
+        if target == Some(inner.node_id.as_str()) {
+            inner.publish_local(topic, message.into());
+            return Ok(());
+        }
+
+        let packet_type = match command {
+            "EVENT" => PacketType::Event,
+            "REQ" => PacketType::Request,
+            "RES" => PacketType::Response,
+            "PING" => PacketType::Ping,
+            "PONG" => PacketType::Pong,
+            "INFO" => return Ok(inner.update_local_info(&message)?),
+            "HEARTBEAT" => return Ok(inner.update_local_cpu(&message)?),
+
+            // DISCOVER and DISCONNECT are not sent, gossip keeps track of the nodes
+            _ => return Ok(()),
+        };
+
+        match target {
+            Some(target) => Ok(inner.send(target, packet_type, &message)?),
+            // only targeted packets are sent over TCP
+            None => Ok(()),
+        }
+    }
+
+    async fn disconnect(&self) -> super::Result<()> {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+
+        self.inner.state.lock().unwrap().writers.clear();
+        self.inner.subscribers.clear();
+
+        Ok(())
+    }
+}
+
+impl Inner {
+    /// Add the nodes from the `urls` option, ex: `127.0.0.1:6000/node-1`
+    async fn add_urls(&self) {
+        for url in &self.options.urls {
+            let url = url.trim_start_matches("tcp://");
+
+            let (address, node_id) = match url.split_once('/') {
+                Some(parts) => parts,
+                None => {
+                    warn!("Invalid TCP url '{}', expected 'host:port/nodeID'", url);
+                    continue;
+                }
+            };
+
+            match tokio::net::lookup_host(address).await.map(|mut a| a.next()) {
+                Ok(Some(address)) => self.discovered(node_id, address),
+                _ => warn!(
+                    "Unable to resolve address of node '{}': {}",
+                    node_id, address
+                ),
+            }
+        }
+    }
+
+    /// A node was found through UDP or the `urls` option, the address of online nodes is kept
+    fn discovered(&self, node_id: &str, address: SocketAddr) {
+        if node_id == self.node_id {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let node = state.nodes.entry(node_id.to_string()).or_default();
+
+        if !node.online || node.address.is_none() {
+            debug!("Discovered node '{}' at {}", node_id, address);
+            node.address = Some(address);
+        }
+    }
+
+    fn topic(&self, command: &str, target: Option<&str>) -> String {
+        match target {
+            Some(target) => format!("{}.{}.{}", self.prefix, command, target),
+            None => format!("{}.{}", self.prefix, command),
+        }
+    }
+
+    /// Hand a packet to the channels of this node
+    fn publish_local(&self, topic: &str, message: Bytes) {
+        self.subscribers.publish(topic, message);
+    }
+
+    fn update_local_info(&self, message: &[u8]) -> Result<()> {
+        let mut info: Value = self
+            .serializer
+            .deserialize_packet(PacketKind::Info, message)?;
+
+        if let Some(info) = info.as_object_mut() {
+            info.remove("ver");
+            info.remove("sender");
+            info.insert("port".to_string(), self.port.into());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.local.info.as_ref() != Some(&info) {
+            state.local.seq += 1;
+            state.local.info = Some(info);
+        }
+
+        Ok(())
+    }
+
+    fn update_local_cpu(&self, message: &[u8]) -> Result<()> {
+        let heartbeat: incoming::HeartbeatMessage = self.serializer.deserialize(message)?;
+
+        let mut state = self.state.lock().unwrap();
+        state.local.cpu_seq += 1;
+        state.local.cpu = heartbeat.cpu;
+
+        Ok(())
+    }
+
+    /// Send a packet to another node, opening a connection if there isn't one yet
+    fn send(
+        self: &Arc<Self>,
+        node_id: &str,
+        packet_type: PacketType,
+        payload: &[u8],
+    ) -> Result<()> {
+        let mut frame = frame::encode(packet_type, payload);
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(writer) = state.writers.get(node_id) {
+            match writer.unbounded_send(frame) {
+                Ok(_) => return Ok(()),
+                Err(err) => frame = err.into_inner(),
+            }
+        }
+
+        let address = state
+            .nodes
+            .get(node_id)
+            .and_then(|node| node.address)
+            .ok_or_else(|| Error::UnknownNode(node_id.to_string()))?;
+
+        let (tx, rx) = mpsc::unbounded();
+        tx.unbounded_send(frame)
+            .expect("receiver is alive because it was just created");
+        state.writers.insert(node_id.to_string(), tx);
+
+        tokio::spawn(Arc::clone(self).write(node_id.to_string(), address, rx));
+
+        Ok(())
+    }
+
+    async fn write(
+        self: Arc<Self>,
+        node_id: String,
+        address: SocketAddr,
+        mut rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    ) {
+        if let Err(err) = self.write_packets(address, &mut rx).await {
+            warn!(
+                "Connection to node '{}' at {} failed: {}",
+                node_id, address, err
+            );
+        }
+
+        // the next packet opens a new connection
+        let mut state = self.state.lock().unwrap();
+        if state
+            .writers
+            .get(&node_id)
+            .is_some_and(|writer| writer.is_connected_to(&rx))
+        {
+            state.writers.remove(&node_id);
+        }
+    }
+
+    async fn write_packets(
+        &self,
+        address: SocketAddr,
+        rx: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+    ) -> Result<()> {
+        let mut socket = TcpStream::connect(address).await?;
+        socket.set_nodelay(true)?;
+
+        let hello = HelloMessage {
+            ver: "4".to_string(),
+            sender: self.node_id.clone(),
+            host: self.host.clone(),
+            port: self.port,
+        };
+        let hello = self.serializer.serialize(hello)?;
+        socket
+            .write_all(&frame::encode(PacketType::GossipHello, &hello))
+            .await?;
+
+        while let Some(frame) = rx.next().await {
+            socket.write_all(&frame).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((socket, address)) => {
+                    tokio::spawn(Arc::clone(&self).read(socket, address));
+                }
+                Err(err) => warn!("Unable to accept TCP connection: {}", err),
+            }
+        }
+    }
+
+    async fn read(self: Arc<Self>, socket: TcpStream, address: SocketAddr) {
+        let mut reader = BufReader::new(socket);
+
+        loop {
+            match frame::read(&mut reader, self.options.max_packet_size).await {
+                Ok(Some((packet_type, payload))) => {
+                    if let Err(err) = self.handle_packet(packet_type, payload, address) {
+                        warn!("Unable to handle {:?} packet: {}", packet_type, err)
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("Closing connection from {}: {}", address, err);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle_packet(
+        self: &Arc<Self>,
+        packet_type: PacketType,
+        payload: Bytes,
+        address: SocketAddr,
+    ) -> Result<()> {
+        let node_id = Some(self.node_id.as_str());
+
+        match packet_type {
+            PacketType::Event => self.publish_local(&self.topic("EVENT", node_id), payload),
+            PacketType::Request => self.publish_local(&self.topic("REQ", node_id), payload),
+            PacketType::Response => self.publish_local(&self.topic("RES", node_id), payload),
+            PacketType::Ping => self.publish_local(&self.topic("PING", node_id), payload),
+            PacketType::Pong => self.publish_local(&self.topic("PONG", node_id), payload),
+            PacketType::GossipHello => {
+                let hello: HelloMessage = self.serializer.deserialize(&payload)?;
+                self.discovered(&hello.sender, SocketAddr::new(address.ip(), hello.port));
+            }
+            PacketType::GossipRequest => {
+                let request: RequestMessage = self.serializer.deserialize(&payload)?;
+                self.handle_gossip_request(request)?;
+            }
+            PacketType::GossipResponse => {
+                let response: ResponseMessage = self.serializer.deserialize(&payload)?;
+                self.handle_gossip_response(response)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn gossip(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.options.gossip_period);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = self.send_gossip_request() {
+                warn!("Unable to send GOSSIP_REQ: {}", err)
+            }
+        }
+    }
+
+    /// Send what we know to a random online node, and sometimes to a random offline node
+    fn send_gossip_request(self: &Arc<Self>) -> Result<()> {
+        let (request, targets) = {
+            let state = self.state.lock().unwrap();
+
+            let mut request = RequestMessage {
+                ver: "4".to_string(),
+                sender: self.node_id.clone(),
+                online: HashMap::new(),
+                offline: HashMap::new(),
+            };
+
+            if state.local.info.is_some() {
+                let local = &state.local;
+                request
+                    .online
+                    .insert(self.node_id.clone(), (local.seq, local.cpu_seq, local.cpu));
+            }
+
+            let mut online = vec![];
+            let mut offline = vec![];
+
+            for (node_id, node) in &state.nodes {
+                if node.info.is_some() {
+                    if node.online {
+                        request
+                            .online
+                            .insert(node_id.clone(), (node.seq, node.cpu_seq, node.cpu));
+                    } else {
+                        request.offline.insert(node_id.clone(), node.seq);
+                    }
+                }
+
+                if node.address.is_some() {
+                    if node.online {
+                        online.push(node_id.clone());
+                    } else {
+                        offline.push(node_id.clone());
+                    }
+                }
+            }
+
+            let mut rng = thread_rng();
+            let mut targets: Vec<String> = online.choose(&mut rng).cloned().into_iter().collect();
+
+            let offline_chance = offline.len() as f64 / (online.len() + 1) as f64;
+            if !offline.is_empty() && rng.gen_bool(offline_chance.min(1.0)) {
+                targets.extend(offline.choose(&mut rng).cloned());
+            }
+
+            (request, targets)
+        };
+
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let request = self.serializer.serialize(request)?;
+        for target in targets {
+            self.send(&target, PacketType::GossipRequest, &request)?;
+        }
+
+        Ok(())
+    }
+
+    /// Answer with the nodes the sender doesn't know about or has older data for
+    fn handle_gossip_request(self: &Arc<Self>, request: RequestMessage) -> Result<()> {
+        let (response, sender_cpu) = {
+            let state = self.state.lock().unwrap();
+
+            let sender_cpu = state
+                .nodes
+                .get(&request.sender)
+                .filter(|node| node.online)
+                .map(|node| node.cpu);
+
+            let mut response = ResponseMessage {
+                ver: "4".to_string(),
+                sender: self.node_id.clone(),
+                online: HashMap::new(),
+                offline: HashMap::new(),
+            };
+
+            let local = &state.local;
+            if let Some(info) = &local.info {
+                let update = match request.online.get(&self.node_id) {
+                    Some((seq, _, _)) if *seq >= local.seq => {
+                        OnlineUpdate::Cpu(local.cpu_seq, local.cpu)
+                    }
+                    _ => OnlineUpdate::Info(with_seq(info, local.seq), local.cpu_seq, local.cpu),
+                };
+
+                response.online.insert(self.node_id.clone(), update);
+            }
+
+            for (node_id, node) in &state.nodes {
+                let info = match &node.info {
+                    Some(info) if node_id != &request.sender => info,
+                    _ => continue,
+                };
+
+                match (node.online, request.online.get(node_id)) {
+                    (true, Some((seq, cpu_seq, _))) if *seq >= node.seq => {
+                        if *cpu_seq < node.cpu_seq {
+                            let update = OnlineUpdate::Cpu(node.cpu_seq, node.cpu);
+                            response.online.insert(node_id.clone(), update);
+                        }
+                    }
+                    (true, _) => {
+                        let update =
+                            OnlineUpdate::Info(with_seq(info, node.seq), node.cpu_seq, node.cpu);
+                        response.online.insert(node_id.clone(), update);
+                    }
+                    (false, Some(_)) => {
+                        response.offline.insert(node_id.clone(), node.seq);
+                    }
+                    (false, None) => {}
+                }
+            }
+
+            (response, sender_cpu)
+        };
+
+        // hearing from a node means it is still alive
+        if let Some(cpu) = sender_cpu {
+            self.publish_heartbeat(&request.sender, cpu)?;
+        }
+
+        if response.online.is_empty() && response.offline.is_empty() {
+            return Ok(());
+        }
+
+        let response = self.serializer.serialize(response)?;
+        self.send(&request.sender, PacketType::GossipResponse, &response)
+    }
+
+    /// Update the nodes and pass the changes to the channels as INFO, HEARTBEAT and DISCONNECT packets
+    fn handle_gossip_response(&self, response: ResponseMessage) -> Result<()> {
+        let mut infos = vec![];
+        let mut heartbeats = vec![];
+        let mut disconnected = vec![];
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            for (node_id, update) in response.online {
+                if node_id == self.node_id {
+                    continue;
+                }
+
+                let node = state.nodes.entry(node_id.clone()).or_default();
+
+                let (cpu_seq, cpu) = match update {
+                    OnlineUpdate::Info(info, cpu_seq, cpu) => {
+                        let seq = info.get("seq").and_then(Value::as_u64).unwrap_or(1);
+                        let restarted = node.instance_id() != info.get("instanceID");
+
+                        if !node.online || restarted || seq > node.seq {
+                            if node.address.is_none() {
+                                node.address = address_from_info(&info);
+                            }
+
+                            node.seq = seq;
+                            node.online = true;
+                            node.info = Some(info.clone());
+                            infos.push((node_id.clone(), info));
+                        }
+
+                        (cpu_seq, cpu)
+                    }
+                    OnlineUpdate::Cpu(cpu_seq, cpu) => (cpu_seq, cpu),
+                };
+
+                if node.online {
+                    node.cpu_seq = node.cpu_seq.max(cpu_seq);
+                    node.cpu = cpu;
+                    heartbeats.push((node_id, cpu));
+                }
+            }
+
+            for (node_id, seq) in response.offline {
+                if let Some(node) = state.nodes.get_mut(&node_id) {
+                    if node.online && seq >= node.seq {
+                        node.online = false;
+                        disconnected.push(node_id);
+                    }
+                }
+            }
+        }
+
+        for (node_id, info) in infos {
+            self.publish_info(&node_id, info)?;
+        }
+
+        for (node_id, cpu) in heartbeats {
+            self.publish_heartbeat(&node_id, cpu)?;
+        }
+
+        for node_id in disconnected {
+            let disconnect = outgoing::DisconnectMessage::new(&node_id);
+            let disconnect = self.serializer.serialize(disconnect)?;
+            self.publish_local(&self.topic("DISCONNECT", None), disconnect.into());
+        }
+
+        Ok(())
+    }
+
+    fn publish_info(&self, node_id: &str, mut info: Value) -> Result<()> {
+        if let Some(info) = info.as_object_mut() {
+            info.insert("ver".to_string(), "4".into());
+            info.insert("sender".to_string(), node_id.into());
+        }
+
+        let info = self.serializer.serialize_packet(PacketKind::Info, info)?;
+        self.publish_local(&self.topic("INFO", None), info.into());
+
+        Ok(())
+    }
+
+    fn publish_heartbeat(&self, node_id: &str, cpu: f32) -> Result<()> {
+        let heartbeat = outgoing::HeartbeatMessage::new(node_id, cpu);
+        let heartbeat = self.serializer.serialize(heartbeat)?;
+        self.publish_local(&self.topic("HEARTBEAT", None), heartbeat.into());
+
+        Ok(())
+    }
+
+    async fn udp_listen(self: Arc<Self>, socket: Arc<UdpSocket>) {
+        let mut buf = [0; 1024];
+
+        loop {
+            match socket.recv_from(&mut buf).await {
+                Ok((len, address)) => {
+                    if let Some((node_id, port)) = udp::parse(&buf[..len], &self.namespace) {
+                        self.discovered(node_id, SocketAddr::new(address.ip(), port));
+                    }
+                }
+                Err(err) => warn!("Unable to receive UDP message: {}", err),
+            }
+        }
+    }
+
+    async fn udp_announce(self: Arc<Self>, socket: Arc<UdpSocket>) {
+        let message = udp::message(&self.namespace, &self.node_id, self.port);
+        let mut interval = tokio::time::interval(self.options.udp_period);
+
+        loop {
+            interval.tick().await;
+
+            for destination in udp::destinations(&self.options) {
+                if let Err(err) = socket.send_to(message.as_bytes(), destination).await {
+                    warn!("Unable to send UDP message to {}: {}", destination, err)
+                }
+            }
+        }
+    }
+}
+
+fn with_seq(info: &Value, seq: u64) -> Value {
+    let mut info = info.clone();
+
+    if let Some(info) = info.as_object_mut() {
+        info.insert("seq".to_string(), seq.into());
+    }
+
+    info
+}
+
+/// Address of a node learned through gossip, using the first IP and the port in its INFO
+fn address_from_info(info: &Value) -> Option<SocketAddr> {
+    let ip: IpAddr = info.get("ipList")?.get(0)?.as_str()?.parse().ok()?;
+    let port = info.get("port")?.as_u64()?;
+
+    Some(SocketAddr::new(ip, u16::try_from(port).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt as _;
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::TcpOptionsBuilder;
+
+    fn inner(node_id: &str) -> Arc<Inner> {
+        Arc::new(Inner {
+            node_id: node_id.to_string(),
+            namespace: String::new(),
+            prefix: "MOL".to_string(),
+            serializer: Serializer::Json,
+            options: TcpOptionsBuilder::default().build(),
+            host: "127.0.0.1".to_string(),
+            port: 6000,
+            state: Mutex::new(State::default()),
+            subscribers: Subscribers::default(),
+        })
+    }
+
+    fn info(instance_id: &str, seq: u64) -> Value {
+        json!({"instanceID": instance_id, "ipList": ["10.0.0.2"], "port": 6001, "seq": seq})
+    }
+
+    fn add_node(inner: &Inner, node_id: &str, node: Node) {
+        inner
+            .state
+            .lock()
+            .unwrap()
+            .nodes
+            .insert(node_id.to_string(), node);
+    }
+
+    /// Packets sent to the node end up in the returned receiver instead of a connection
+    fn capture(inner: &Inner, node_id: &str) -> mpsc::UnboundedReceiver<Vec<u8>> {
+        let (tx, rx) = mpsc::unbounded();
+        inner
+            .state
+            .lock()
+            .unwrap()
+            .writers
+            .insert(node_id.to_string(), tx);
+        rx
+    }
+
+    async fn gossip_response(rx: &mut mpsc::UnboundedReceiver<Vec<u8>>) -> ResponseMessage {
+        let frame = rx.next().now_or_never().flatten().expect("no packet sent");
+        let (packet_type, payload) = frame::read(&mut frame.as_slice(), usize::MAX)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(packet_type, PacketType::GossipResponse);
+        Serializer::Json.deserialize(&payload).unwrap()
+    }
+
+    fn received(subscription: &mut Subscription) -> Option<Value> {
+        let message = subscription.next().now_or_never().flatten()?;
+        Some(serde_json::from_slice(&message).unwrap())
+    }
+
+    #[tokio::test]
+    async fn gossip_request_is_answered_with_unknown_nodes() {
+        let inner = inner("node-a");
+        inner.state.lock().unwrap().local = LocalNode {
+            info: Some(info("a", 1)),
+            seq: 1,
+            cpu_seq: 1,
+            cpu: 5.0,
+        };
+        add_node(
+            &inner,
+            "node-c",
+            Node {
+                info: Some(info("c", 2)),
+                seq: 2,
+                cpu_seq: 3,
+                cpu: 10.0,
+                address: None,
+                online: true,
+            },
+        );
+        let mut rx = capture(&inner, "node-b");
+
+        let request = RequestMessage {
+            ver: "4".to_string(),
+            sender: "node-b".to_string(),
+            online: HashMap::new(),
+            offline: HashMap::new(),
+        };
+        inner.handle_gossip_request(request).unwrap();
+
+        let response = gossip_response(&mut rx).await;
+        assert_eq!(response.sender, "node-a");
+        assert!(matches!(
+            &response.online["node-a"],
+            OnlineUpdate::Info(info, 1, cpu) if info["seq"] == 1 && *cpu == 5.0
+        ));
+        assert!(matches!(
+            &response.online["node-c"],
+            OnlineUpdate::Info(info, 3, cpu) if info["instanceID"] == "c" && *cpu == 10.0
+        ));
+        assert!(response.offline.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gossip_request_only_gets_newer_data() {
+        let inner = inner("node-a");
+        inner.state.lock().unwrap().local = LocalNode {
+            info: Some(info("a", 1)),
+            seq: 1,
+            cpu_seq: 4,
+            cpu: 5.0,
+        };
+        let node = |seq, cpu_seq, online| Node {
+            info: Some(info("x", seq)),
+            seq,
+            cpu_seq,
+            cpu: 10.0,
+            address: None,
+            online,
+        };
+        add_node(&inner, "up-to-date", node(2, 3, true));
+        add_node(&inner, "newer-cpu", node(2, 5, true));
+        add_node(&inner, "went-offline", node(2, 3, false));
+        let mut rx = capture(&inner, "node-b");
+
+        let request = RequestMessage {
+            ver: "4".to_string(),
+            sender: "node-b".to_string(),
+            online: vec![
+                ("node-a".to_string(), (1, 1, 0.0)),
+                ("up-to-date".to_string(), (2, 3, 10.0)),
+                ("newer-cpu".to_string(), (2, 3, 10.0)),
+                ("went-offline".to_string(), (2, 3, 10.0)),
+            ]
+            .into_iter()
+            .collect(),
+            offline: HashMap::new(),
+        };
+        inner.handle_gossip_request(request).unwrap();
+
+        let response = gossip_response(&mut rx).await;
+        assert!(matches!(response.online["node-a"], OnlineUpdate::Cpu(4, _)));
+        assert!(matches!(
+            response.online["newer-cpu"],
+            OnlineUpdate::Cpu(5, _)
+        ));
+        assert!(!response.online.contains_key("up-to-date"));
+        assert_eq!(response.offline.get("went-offline"), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn gossip_response_is_passed_to_the_channels() {
+        let inner = inner("node-a");
+        let mut infos = inner.subscribers.subscribe("MOL.INFO");
+        let mut heartbeats = inner.subscribers.subscribe("MOL.HEARTBEAT");
+        let mut disconnects = inner.subscribers.subscribe("MOL.DISCONNECT");
+
+        let response =
+            |online: Vec<(&str, OnlineUpdate)>, offline: Vec<(&str, u64)>| ResponseMessage {
+                ver: "4".to_string(),
+                sender: "node-c".to_string(),
+                online: online
+                    .into_iter()
+                    .map(|(node_id, update)| (node_id.to_string(), update))
+                    .collect(),
+                offline: offline
+                    .into_iter()
+                    .map(|(node_id, seq)| (node_id.to_string(), seq))
+                    .collect(),
+            };
+
+        // a new node
+        let update = OnlineUpdate::Info(info("b", 1), 1, 20.0);
+        inner
+            .handle_gossip_response(response(vec![("node-b", update)], vec![]))
+            .unwrap();
+
+        let info_packet = received(&mut infos).unwrap();
+        assert_eq!(info_packet["sender"], "node-b");
+        assert_eq!(info_packet["instanceID"], "b");
+        assert_eq!(received(&mut heartbeats).unwrap()["sender"], "node-b");
+        assert_eq!(
+            inner.state.lock().unwrap().nodes["node-b"].address,
+            Some("10.0.0.2:6001".parse().unwrap())
+        );
+
+        // the same info again only updates the cpu
+        let update = OnlineUpdate::Info(info("b", 1), 2, 30.0);
+        inner
+            .handle_gossip_response(response(vec![("node-b", update)], vec![]))
+            .unwrap();
+
+        assert!(received(&mut infos).is_none());
+        assert_eq!(received(&mut heartbeats).unwrap()["cpu"], 30.0);
+
+        // restarted with the same seq
+        let update = OnlineUpdate::Info(info("b-restarted", 1), 1, 0.0);
+        inner
+            .handle_gossip_response(response(vec![("node-b", update)], vec![]))
+            .unwrap();
+
+        assert_eq!(received(&mut infos).unwrap()["instanceID"], "b-restarted");
+
+        // gone offline
+        inner
+            .handle_gossip_response(response(vec![], vec![("node-b", 1)]))
+            .unwrap();
+
+        assert_eq!(received(&mut disconnects).unwrap()["sender"], "node-b");
+        assert!(!inner.state.lock().unwrap().nodes["node-b"].online);
+    }
+}
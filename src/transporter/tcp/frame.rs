@@ -0,0 +1,159 @@
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt as _};
+
+use super::{Error, Result};
+
+/// Header is 1 byte CRC, 4 bytes length (including the header) and 1 byte packet type
+const HEADER_SIZE: usize = 6;
+
+/// Packet types sent over TCP, ids match the moleculerjs TCP transporter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum PacketType {
+    Event = 1,
+    Request = 2,
+    Response = 3,
+    Ping = 4,
+    Pong = 5,
+    GossipRequest = 6,
+    GossipResponse = 7,
+    GossipHello = 8,
+}
+
+impl PacketType {
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Event),
+            2 => Some(Self::Request),
+            3 => Some(Self::Response),
+            4 => Some(Self::Ping),
+            5 => Some(Self::Pong),
+            6 => Some(Self::GossipRequest),
+            7 => Some(Self::GossipResponse),
+            8 => Some(Self::GossipHello),
+            _ => None,
+        }
+    }
+}
+
+fn crc(header: &[u8]) -> u8 {
+    header[1] ^ header[2] ^ header[3] ^ header[4] ^ header[5]
+}
+
+pub(super) fn encode(packet_type: PacketType, payload: &[u8]) -> Vec<u8> {
+    let length = (HEADER_SIZE + payload.len()) as u32;
+
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.push(0);
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.push(packet_type as u8);
+    frame[0] = crc(&frame);
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+/// Read the next packet, returns `None` once the other side closes the connection
+pub(super) async fn read<R>(
+    reader: &mut R,
+    max_packet_size: usize,
+) -> Result<Option<(PacketType, Bytes)>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0; HEADER_SIZE];
+
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    if crc(&header) != header[0] {
+        return Err(Error::InvalidChecksum);
+    }
+
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > max_packet_size {
+        return Err(Error::PacketTooLarge(length));
+    }
+
+    let packet_type = PacketType::from_id(header[5]).ok_or(Error::UnknownPacketType(header[5]))?;
+
+    let mut payload = vec![0; length.saturating_sub(HEADER_SIZE)];
+    reader.read_exact(&mut payload).await?;
+
+    Ok(Some((packet_type, payload.into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_writes_the_header() {
+        let frame = encode(PacketType::Request, b"{}");
+
+        // length includes the 6 bytes of the header
+        assert_eq!(&frame[1..5], &8u32.to_be_bytes());
+        assert_eq!(frame[5], 2);
+        assert_eq!(frame[0], 8 ^ 2);
+        assert_eq!(&frame[6..], b"{}");
+    }
+
+    #[test]
+    fn packet_ids_match_moleculerjs() {
+        let ids = [
+            (PacketType::Event, 1),
+            (PacketType::Request, 2),
+            (PacketType::Response, 3),
+            (PacketType::Ping, 4),
+            (PacketType::Pong, 5),
+            (PacketType::GossipRequest, 6),
+            (PacketType::GossipResponse, 7),
+            (PacketType::GossipHello, 8),
+        ];
+
+        for (packet_type, id) in ids {
+            assert_eq!(packet_type as u8, id);
+            assert_eq!(PacketType::from_id(id), Some(packet_type));
+        }
+        assert_eq!(PacketType::from_id(0), None);
+        assert_eq!(PacketType::from_id(9), None);
+    }
+
+    #[tokio::test]
+    async fn read_returns_the_encoded_packets() {
+        let mut stream = encode(PacketType::Event, b"first");
+        stream.extend(encode(PacketType::GossipHello, b""));
+        let mut reader = stream.as_slice();
+
+        let (packet_type, payload) = read(&mut reader, 1024).await.unwrap().unwrap();
+        assert_eq!(packet_type, PacketType::Event);
+        assert_eq!(payload.as_ref(), b"first");
+
+        let (packet_type, payload) = read(&mut reader, 1024).await.unwrap().unwrap();
+        assert_eq!(packet_type, PacketType::GossipHello);
+        assert!(payload.is_empty());
+
+        // connection closed
+        assert!(read(&mut reader, 1024).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn read_rejects_invalid_packets() {
+        let mut frame = encode(PacketType::Event, b"payload");
+        frame[0] ^= 0xff;
+        let result = read(&mut frame.as_slice(), 1024).await;
+        assert!(matches!(result, Err(Error::InvalidChecksum)));
+
+        let frame = encode(PacketType::Event, &[0; 100]);
+        let result = read(&mut frame.as_slice(), 50).await;
+        assert!(matches!(result, Err(Error::PacketTooLarge(106))));
+
+        let mut frame = encode(PacketType::Event, b"payload");
+        frame[5] = 42;
+        frame[0] = crc(&frame);
+        let result = read(&mut frame.as_slice(), 1024).await;
+        assert!(matches!(result, Err(Error::UnknownPacketType(42))));
+    }
+}
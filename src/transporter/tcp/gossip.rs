@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{Packet, PacketKind};
+
+/// Sent first on every new connection so the other node knows where to connect back to
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct HelloMessage {
+    pub(super) ver: String,
+    pub(super) sender: String,
+    pub(super) host: String,
+    pub(super) port: u16,
+}
+
+/// Tells another node which nodes we know about and how up to date they are
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct RequestMessage {
+    pub(super) ver: String,
+    pub(super) sender: String,
+
+    /// `nodeID: [seq, cpuSeq, cpu]`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(super) online: HashMap<String, (u64, u64, f32)>,
+
+    /// `nodeID: seq`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(super) offline: HashMap<String, u64>,
+}
+
+/// Answers a [RequestMessage] with the nodes the other node is missing or has older data for
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct ResponseMessage {
+    pub(super) ver: String,
+    pub(super) sender: String,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(super) online: HashMap<String, OnlineUpdate>,
+
+    /// `nodeID: seq`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(super) offline: HashMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub(super) enum OnlineUpdate {
+    /// `[info, cpuSeq, cpu]`
+    Info(Value, u64, f32),
+    /// `[cpuSeq, cpu]`
+    Cpu(u64, f32),
+}
+
+impl Packet for HelloMessage {
+    const KIND: PacketKind = PacketKind::GossipHello;
+}
+
+impl Packet for RequestMessage {
+    const KIND: PacketKind = PacketKind::GossipRequest;
+}
+
+impl Packet for ResponseMessage {
+    const KIND: PacketKind = PacketKind::GossipResponse;
+}
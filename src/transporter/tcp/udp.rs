@@ -0,0 +1,80 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::config::TcpOptions;
+
+/// Bind the discovery socket, several nodes on the same host share the same port
+pub(super) fn bind(options: &TcpOptions) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(options.udp_broadcast.is_some())?;
+    socket.set_nonblocking(true)?;
+
+    let bind_address = options.udp_bind_address.unwrap_or(Ipv4Addr::UNSPECIFIED);
+    socket.bind(&SocketAddrV4::new(bind_address, options.udp_port).into())?;
+
+    if let Some(group) = options.udp_multicast {
+        socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(options.udp_multicast_ttl)?;
+    }
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Addresses the node is announced to
+pub(super) fn destinations(options: &TcpOptions) -> impl Iterator<Item = SocketAddr> + '_ {
+    options
+        .udp_multicast
+        .into_iter()
+        .chain(options.udp_broadcast)
+        .map(move |ip| SocketAddr::V4(SocketAddrV4::new(ip, options.udp_port)))
+}
+
+/// Announcement in the moleculerjs format: `namespace|nodeID|port`
+pub(super) fn message(namespace: &str, node_id: &str, port: u16) -> String {
+    format!("{}|{}|{}", namespace, node_id, port)
+}
+
+/// Returns the node id and TCP port of an announcement from the same namespace
+pub(super) fn parse<'a>(message: &'a [u8], namespace: &str) -> Option<(&'a str, u16)> {
+    let message = std::str::from_utf8(message).ok()?;
+
+    let mut parts = message.split('|');
+    let (ns, node_id, port) = (parts.next()?, parts.next()?, parts.next()?);
+
+    if parts.next().is_some() || ns != namespace {
+        return None;
+    }
+
+    Some((node_id, port.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_announcements_of_the_namespace() {
+        let announcement = message("prod", "node-1", 6000);
+        assert_eq!(announcement, "prod|node-1|6000");
+
+        assert_eq!(
+            parse(announcement.as_bytes(), "prod"),
+            Some(("node-1", 6000))
+        );
+        assert_eq!(parse(b"|node-1|6000", ""), Some(("node-1", 6000)));
+    }
+
+    #[test]
+    fn parse_ignores_other_messages() {
+        assert_eq!(parse(b"prod|node-1|6000", "dev"), None);
+        assert_eq!(parse(b"prod|node-1", "prod"), None);
+        assert_eq!(parse(b"prod|node-1|6000|extra", "prod"), None);
+        assert_eq!(parse(b"prod|node-1|port", "prod"), None);
+        assert_eq!(parse(b"prod|node-1|70000", "prod"), None);
+        assert_eq!(parse(&[0xff, 0xfe], "prod"), None);
+    }
+}
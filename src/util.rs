@@ -2,6 +2,7 @@ use std::borrow::Cow;
 
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde_json::Value;
 
 fn random_string_iter(take: usize) -> impl Iterator<Item = char> {
     thread_rng()
@@ -44,3 +45,15 @@ pub(crate) fn ip_list() -> Vec<String> {
         .map(|ip| ip.to_string())
         .collect()
 }
+
+/// Merge the keys of `overrides` into `meta`, if either one is not an object `overrides` replaces `meta`.
+pub(crate) fn merge_meta(meta: Value, overrides: Option<&Value>) -> Value {
+    match (meta, overrides) {
+        (Value::Object(mut meta), Some(Value::Object(overrides))) => {
+            meta.extend(overrides.clone());
+            Value::Object(meta)
+        }
+        (_, Some(overrides)) => overrides.clone(),
+        (meta, None) => meta,
+    }
+}
@@ -0,0 +1,111 @@
+//! Helpers shared by the integration tests.
+
+// each test binary uses a different subset of the helpers
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use moleculer::{
+    config::{ConfigBuilder, Transporter},
+    service::Service,
+    Error, ServiceBroker,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Config for a broker on the in-memory transporter, brokers sharing the `namespace` can see each other.
+pub fn config(namespace: &str, node_id: &str) -> ConfigBuilder {
+    ConfigBuilder::default()
+        .namespace(namespace)
+        .node_id(node_id)
+        .transporter(Transporter::fake())
+        .heartbeat_interval(1u32)
+}
+
+/// A namespace no other test uses, the in-memory transporter is shared by the whole test binary.
+pub fn namespace() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Start the broker in the background and return a handle to it.
+pub fn start(config: ConfigBuilder, services: Vec<Service>) -> ServiceBroker {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let broker = ServiceBroker::new(config.build()).add_services(services);
+    tokio::spawn(broker.clone().start());
+    broker
+}
+
+/// Wait until a node with the action has been discovered by the broker.
+pub async fn wait_for_action(broker: &ServiceBroker, action: &str, params: Value) {
+    for _ in 0..100 {
+        match broker.clone().call(action, params.clone()).await {
+            Err(Error::ServiceNotFound(_)) => tokio::time::sleep(Duration::from_millis(50)).await,
+            _ => return,
+        }
+    }
+
+    panic!("action '{}' was never discovered", action);
+}
+
+/// Two brokers on the transporter call an action and send events to each other,
+/// used by the tests of the transporters that need an external server
+pub async fn check_transporter(transporter: impl Fn() -> Transporter) {
+    check_brokers(|config| config.transporter(transporter())).await
+}
+
+/// Two brokers with the changed config call an action and send events to each other
+pub async fn check_brokers(change_config: impl Fn(ConfigBuilder) -> ConfigBuilder) {
+    use moleculer::{
+        service::{ActionBuilder, EventBuilder},
+        ActionContext, EventContext,
+    };
+    use serde_json::json;
+
+    let namespace = namespace();
+
+    let add = ActionBuilder::new("math.add")
+        .add_callback(|ctx: ActionContext| {
+            let sum = ctx.params["a"].as_i64().unwrap_or_default()
+                + ctx.params["b"].as_i64().unwrap_or_default();
+            Ok::<_, Box<dyn std::error::Error>>(sum)
+        })
+        .build();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let created = EventBuilder::new("user.created")
+        .add_callback(move |ctx: EventContext| tx.send(ctx.params).map_err(|err| err.to_string()))
+        .build();
+
+    start(
+        change_config(config(&namespace, "service-node")),
+        vec![Service::new("math").add_action(add).add_event(created)],
+    );
+    let broker = start(change_config(config(&namespace, "caller")), vec![]);
+
+    wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    let result = broker
+        .clone()
+        .call("math.add", json!({"a": 1, "b": 2}))
+        .await
+        .unwrap();
+    assert_eq!(result, json!(3));
+
+    broker.emit("user.created", json!({"id": 1})).await.unwrap();
+    broker
+        .broadcast("user.created", json!({"id": 2}))
+        .await
+        .unwrap();
+
+    // callbacks run on their own task, in any order
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let params = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        ids.push(params["id"].as_i64().unwrap());
+    }
+    ids.sort_unstable();
+    assert_eq!(ids, vec![1, 2]);
+}
@@ -0,0 +1,645 @@
+//! Brokers talking to each other over the in-memory transporter.
+
+mod common;
+
+use std::{
+    error::Error as StdError,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use moleculer::{
+    config::{FakeLink, Transporter},
+    service::{ActionBuilder, CallOptionsBuilder, EventBuilder, Service},
+    ActionContext, Error, EventContext, MoleculerError,
+};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+type BoxError = Box<dyn StdError>;
+
+fn math_service() -> Service {
+    let add = ActionBuilder::new("math.add")
+        .add_callback(|ctx: ActionContext| {
+            Ok::<_, BoxError>(ctx.params["a"].as_i64().unwrap() + ctx.params["b"].as_i64().unwrap())
+        })
+        .build();
+
+    Service::new("math").add_action(add)
+}
+
+/// Service with an event that forwards everything it receives to the returned channel
+fn listener_service(name: &str, event: &str) -> (Service, mpsc::UnboundedReceiver<EventContext>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let event = EventBuilder::new(event)
+        .add_callback(move |ctx: EventContext| tx.send(ctx).map_err(|err| err.to_string()))
+        .build();
+
+    // the action is only there to know when the service has been discovered
+    let ready = ActionBuilder::new(format!("{}.ready", name))
+        .add_callback(|_ctx: ActionContext| Ok::<_, BoxError>(true))
+        .build();
+
+    let service = Service::new(name).add_event(event).add_action(ready);
+    (service, rx)
+}
+
+/// Count the events received within the time
+async fn received(rx: &mut mpsc::UnboundedReceiver<EventContext>, within: Duration) -> usize {
+    tokio::time::sleep(within).await;
+
+    let mut count = 0;
+    while rx.try_recv().is_ok() {
+        count += 1;
+    }
+    count
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn call_action_on_other_node() {
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "math-node"),
+        vec![math_service()],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    let result = broker
+        .call("math.add", json!({"a": 1, "b": 2}))
+        .await
+        .unwrap();
+    assert_eq!(result, json!(3));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nodes_started_later_are_discovered_both_ways() {
+    let namespace = common::namespace();
+    let first = common::start(common::config(&namespace, "first"), vec![math_service()]);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (listener, _rx) = listener_service("late", "late.event");
+    let second = common::start(common::config(&namespace, "second"), vec![listener]);
+
+    common::wait_for_action(&second, "math.add", json!({"a": 0, "b": 0})).await;
+    common::wait_for_action(&first, "late.ready", Value::Null).await;
+
+    assert_eq!(
+        first.call("late.ready", Value::Null).await.unwrap(),
+        json!(true)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_callback_returns_value() {
+    let slow_double = ActionBuilder::new("async.double")
+        .add_callback(|ctx: ActionContext| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok::<_, MoleculerError>(ctx.params.as_i64().unwrap_or_default() * 2)
+        })
+        .build();
+
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "async-node"),
+        vec![Service::new("async").add_action(slow_double)],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "async.double", json!(0)).await;
+
+    let result = broker.call("async.double", json!(21)).await.unwrap();
+    assert_eq!(result, json!(42));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reply_is_sent_once() {
+    let replies = ActionBuilder::new("reply.early")
+        .add_callback(|ctx: ActionContext| {
+            ctx.reply(json!("from reply"));
+            Ok::<_, BoxError>("from return")
+        })
+        .build();
+
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "reply-node"),
+        vec![Service::new("reply").add_action(replies)],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "reply.early", Value::Null).await;
+
+    let result = broker.call("reply.early", Value::Null).await.unwrap();
+    assert_eq!(result, json!("from reply"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remote_error_is_returned() {
+    let fails = ActionBuilder::new("failing.fail")
+        .add_callback(|_ctx: ActionContext| {
+            let error = MoleculerError::new("not valid")
+                .set_name("ValidationError")
+                .set_code(422)
+                .set_data(json!({"field": "name"}));
+
+            Err::<Value, BoxError>(error.into())
+        })
+        .build();
+
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "failing-node"),
+        vec![Service::new("failing").add_action(fails)],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "failing.fail", Value::Null).await;
+
+    match broker.call("failing.fail", Value::Null).await {
+        Err(Error::Remote(error)) => {
+            assert_eq!(error.name, "ValidationError");
+            assert_eq!(error.message, "not valid");
+            assert_eq!(error.code, 422);
+            assert_eq!(error.data, json!({"field": "name"}));
+        }
+        result => panic!("expected a remote error, got {:?}", result),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn panicking_callback_does_not_stop_the_broker() {
+    let panics = ActionBuilder::new("buggy.panic")
+        .add_callback(|_ctx: ActionContext| -> Result<Value, BoxError> { panic!("callback bug") })
+        .build();
+
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "panic-node"),
+        vec![Service::new("buggy").add_action(panics), math_service()],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    let result = broker
+        .clone()
+        .call_with_timeout("buggy.panic", Value::Null, Duration::from_secs(5))
+        .await;
+    assert!(matches!(result, Err(Error::Remote(_))), "{:?}", result);
+
+    // the node is still handling requests
+    let result = broker
+        .call("math.add", json!({"a": 1, "b": 1}))
+        .await
+        .unwrap();
+    assert_eq!(result, json!(2));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_action_times_out() {
+    let slow = ActionBuilder::new("slow.slow")
+        .add_callback(|_ctx: ActionContext| async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok::<_, MoleculerError>(Value::Null)
+        })
+        .build();
+
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "slow-node"),
+        vec![Service::new("slow").add_action(slow), math_service()],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    // whichever of the caller and the slow node gives up first
+    for _ in 0..5 {
+        let result = broker
+            .clone()
+            .call_with_timeout("slow.slow", Value::Null, Duration::from_millis(200))
+            .await;
+
+        match result {
+            Err(Error::RequestTimeout(error)) => {
+                assert_eq!(error.action, "slow.slow");
+                assert_eq!(error.node_id, "slow-node");
+            }
+            result => panic!("expected a timeout, got {:?}", result),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_services_fail_right_away() {
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "math-node"),
+        vec![math_service()],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    let result = broker.clone().call("math.subtract", Value::Null).await;
+    assert!(matches!(result, Err(Error::ServiceNotFound(action)) if action == "math.subtract"));
+
+    let options = CallOptionsBuilder::default().node_id("caller").build();
+    let result = broker
+        .clone()
+        .call_with_options("math.add", json!({"a": 1, "b": 1}), options)
+        .await;
+    assert!(matches!(
+        result,
+        Err(Error::ServiceNotAvailable { action, node_id }) if action == "math.add" && node_id == "caller"
+    ));
+
+    let result = broker.emit("math.nobody", Value::Null).await;
+    assert!(matches!(result, Err(Error::EventHandlerNotFound(event)) if event == "math.nobody"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn call_options_are_applied() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&attempts);
+
+    let flaky = ActionBuilder::new("flaky.flaky")
+        .add_callback(move |ctx: ActionContext| {
+            // fail the first two attempts
+            if counted.fetch_add(1, Ordering::SeqCst) < 2 {
+                return Err::<_, BoxError>(MoleculerError::new("busy").set_retryable(true).into());
+            }
+
+            Ok(ctx.meta)
+        })
+        .build();
+
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "flaky-node"),
+        vec![Service::new("flaky").add_action(flaky), math_service()],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    let options = CallOptionsBuilder::default()
+        .node_id("flaky-node")
+        .meta(json!({"user": "john"}))
+        .retries(2u32)
+        .build();
+
+    let meta = broker
+        .call_with_options("flaky.flaky", Value::Null, options)
+        .await
+        .unwrap();
+
+    assert_eq!(meta, json!({"user": "john"}));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn context_is_carried_to_child_calls() {
+    let first = ActionBuilder::new("chain.first")
+        .add_callback(|ctx: ActionContext| async move {
+            let id = ctx.id.clone();
+            let request_id = ctx.request_id.clone();
+            let child = ctx.call("chainEnd.second", Value::Null).await?;
+
+            Ok::<_, Error>(json!({"id": id, "requestID": request_id, "child": child}))
+        })
+        .build();
+
+    let second = ActionBuilder::new("chainEnd.second")
+        .add_callback(|ctx: ActionContext| {
+            Ok::<_, BoxError>(json!({
+                "parentID": ctx.parent_id,
+                "requestID": ctx.request_id,
+                "caller": ctx.caller,
+                "level": ctx.level,
+                "meta": ctx.meta,
+            }))
+        })
+        .build();
+
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "chain-node"),
+        vec![Service::new("chain").add_action(first)],
+    );
+    let end = common::start(
+        common::config(&namespace, "end-node"),
+        vec![Service::new("chainEnd").add_action(second)],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    common::wait_for_action(&broker, "chainEnd.second", Value::Null).await;
+    common::wait_for_action(&end, "chain.first", Value::Null).await;
+
+    let options = CallOptionsBuilder::default()
+        .meta(json!({"user": "john"}))
+        .build();
+    let result = broker
+        .call_with_options("chain.first", Value::Null, options)
+        .await
+        .unwrap();
+
+    let child = &result["child"];
+    assert_eq!(child["parentID"], result["id"]);
+    assert_eq!(child["requestID"], result["requestID"]);
+    assert_eq!(child["caller"], json!("chain"));
+    assert_eq!(child["level"], json!(2));
+    assert_eq!(child["meta"], json!({"user": "john"}));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_call_level_stops_call_loops() {
+    // ping and pong call each other until the level is too high
+    let ping = ActionBuilder::new("loop.ping")
+        .add_callback(|ctx: ActionContext| async move { ctx.call("loop.pong", Value::Null).await })
+        .build();
+    let pong = ActionBuilder::new("loop.pong")
+        .add_callback(|ctx: ActionContext| async move { ctx.call("loop.ping", Value::Null).await })
+        .build();
+
+    let namespace = common::namespace();
+    let ping_node = common::start(
+        common::config(&namespace, "ping-node").max_call_level(3u32),
+        vec![Service::new("loop").add_action(ping), math_service()],
+    );
+    let (pong_ready, _rx) = listener_service("pongReady", "pong.unused");
+    let pong_node = common::start(
+        common::config(&namespace, "pong-node").max_call_level(3u32),
+        vec![Service::new("loop").add_action(pong), pong_ready],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    // the loop can only start once both nodes know each other
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+    common::wait_for_action(&pong_node, "math.add", json!({"a": 0, "b": 0})).await;
+    common::wait_for_action(&ping_node, "pongReady.ready", Value::Null).await;
+
+    match broker.call("loop.ping", Value::Null).await {
+        Err(Error::Remote(error)) => {
+            assert!(
+                error.message.contains("reached the limit (4)"),
+                "{}",
+                error.message
+            )
+        }
+        result => panic!("expected the max call level error, got {:?}", result),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn emit_reaches_one_node_broadcast_reaches_all() {
+    let namespace = common::namespace();
+    let (listener, mut first_rx) = listener_service("listener", "user.created");
+    common::start(common::config(&namespace, "first"), vec![listener]);
+    let (listener, mut second_rx) = listener_service("listener", "user.created");
+    common::start(common::config(&namespace, "second"), vec![listener]);
+    let broker = common::start(common::config(&namespace, "emitter"), vec![]);
+
+    common::wait_for_action(&broker, "listener.ready", json!({})).await;
+    // both listeners have to be known before counting
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    broker.emit("user.created", json!({"id": 1})).await.unwrap();
+
+    let emitted = received(&mut first_rx, Duration::from_millis(200)).await
+        + received(&mut second_rx, Duration::ZERO).await;
+    assert_eq!(emitted, 1);
+
+    broker
+        .broadcast("user.created", json!({"id": 2}))
+        .await
+        .unwrap();
+
+    assert_eq!(received(&mut first_rx, Duration::from_millis(200)).await, 1);
+    assert_eq!(received(&mut second_rx, Duration::ZERO).await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn event_context_has_the_event() {
+    let namespace = common::namespace();
+    let (listener, mut rx) = listener_service("listener", "user.created");
+    common::start(common::config(&namespace, "listener-node"), vec![listener]);
+    let broker = common::start(common::config(&namespace, "emitter"), vec![]);
+
+    common::wait_for_action(&broker, "listener.ready", Value::Null).await;
+
+    broker.emit("user.created", json!({"id": 1})).await.unwrap();
+
+    let ctx = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(ctx.params, json!({"id": 1}));
+    assert_eq!(ctx.event_name.as_deref(), Some("user.created"));
+    assert_eq!(ctx.node_id, "emitter");
+    assert_eq!(ctx.service.as_deref(), Some("listener"));
+}
+
+/// `math.add` counting the calls it handled
+fn counted_math_service(calls: &Arc<AtomicUsize>) -> Service {
+    let calls = Arc::clone(calls);
+    let add = ActionBuilder::new("math.add")
+        .add_callback(move |ctx: ActionContext| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, BoxError>(ctx.params["a"].as_i64().unwrap() + ctx.params["b"].as_i64().unwrap())
+        })
+        .build();
+
+    Service::new("math").add_action(add)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn disabled_balancer_lets_the_transporter_balance() {
+    let namespace = common::namespace();
+    let balanced = |node_id| common::config(&namespace, node_id).disable_balancer(true);
+
+    let first_calls = Arc::new(AtomicUsize::new(0));
+    common::start(balanced("first"), vec![counted_math_service(&first_calls)]);
+    let second_calls = Arc::new(AtomicUsize::new(0));
+    common::start(
+        balanced("second"),
+        vec![counted_math_service(&second_calls)],
+    );
+    let broker = common::start(balanced("caller"), vec![]);
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    for _ in 0..20 {
+        let result = broker
+            .clone()
+            .call("math.add", json!({"a": 1, "b": 2}))
+            .await
+            .unwrap();
+        assert_eq!(result, json!(3));
+    }
+
+    // each request goes to a single node of the queue group
+    let first_calls = first_calls.load(Ordering::SeqCst);
+    let second_calls = second_calls.load(Ordering::SeqCst);
+    assert!(first_calls > 0 && second_calls > 0);
+    assert_eq!(first_calls + second_calls, 21);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn disabled_balancer_reaches_services_added_after_start() {
+    let namespace = common::namespace();
+    let balanced = |node_id| common::config(&namespace, node_id).disable_balancer(true);
+
+    let late_node = common::start(balanced("late-node"), vec![]);
+    let broker = common::start(balanced("caller"), vec![]);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (listener, mut rx) = listener_service("listener", "user.created");
+    late_node.add_services(vec![listener]);
+
+    // through the REQB and EVENTB topics of the late service, left unanswered without listeners
+    let ready = common::wait_for_action(&broker, "listener.ready", Value::Null);
+    tokio::time::timeout(Duration::from_secs(5), ready)
+        .await
+        .expect("balanced request not handled");
+
+    broker.emit("user.created", json!({"id": 1})).await.unwrap();
+    assert_eq!(received(&mut rx, Duration::from_millis(200)).await, 1);
+}
+
+/// Service forwarding the `$transporter.connected` and `$transporter.disconnected` events it receives
+fn connection_service() -> (Service, mpsc::UnboundedReceiver<EventContext>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let mut service = Service::new("connection");
+    for event in &["$transporter.connected", "$transporter.disconnected"] {
+        let tx = tx.clone();
+        let event = EventBuilder::new(*event)
+            .add_callback(move |ctx: EventContext| tx.send(ctx).map_err(|err| err.to_string()))
+            .build();
+        service = service.add_event(event);
+    }
+
+    (service, rx)
+}
+
+async fn next_event(rx: &mut mpsc::UnboundedReceiver<EventContext>) -> EventContext {
+    tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("no connection event")
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reconnected_node_announces_itself_again() {
+    let namespace = common::namespace();
+    let link = FakeLink::default();
+
+    let (connection, mut events) = connection_service();
+    let flaky_node = common::start(
+        common::config(&namespace, "flaky-node")
+            .transporter(Transporter::fake_with_link(link.clone())),
+        vec![connection],
+    );
+    let broker = common::start(common::config(&namespace, "caller"), vec![]);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // the `$transporter.connected` of the first connection, if the service was added in time
+    while events.try_recv().is_ok() {}
+    link.disconnect();
+
+    let event = next_event(&mut events).await;
+    assert_eq!(
+        event.event_name.as_deref(),
+        Some("$transporter.disconnected")
+    );
+    assert_eq!(event.params, json!({"graceFul": false}));
+
+    // the INFO of the new service and the one of the new node are lost while disconnected
+    flaky_node.clone().add_services(vec![math_service()]);
+    let (late, _rx) = listener_service("late", "user.created");
+    common::start(common::config(&namespace, "late-node"), vec![late]);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let result = broker
+        .clone()
+        .call("math.add", json!({"a": 1, "b": 2}))
+        .await;
+    assert!(
+        matches!(result, Err(Error::ServiceNotFound(_))),
+        "{:?}",
+        result
+    );
+
+    link.reconnect();
+
+    let event = next_event(&mut events).await;
+    assert_eq!(event.event_name.as_deref(), Some("$transporter.connected"));
+    assert_eq!(event.params, json!({"wasReconnect": true}));
+
+    // found through the INFO sent again and the answers to the DISCOVER
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+    common::wait_for_action(&flaky_node, "late.ready", Value::Null).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn heartbeats_keep_nodes_alive() {
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "math-node"),
+        vec![math_service()],
+    );
+    let broker = common::start(
+        common::config(&namespace, "caller").heartbeat_timeout(3u32),
+        vec![],
+    );
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    // longer than the heartbeat timeout, the node stays known as long as it sends heartbeats
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    let result = broker
+        .call("math.add", json!({"a": 1, "b": 2}))
+        .await
+        .unwrap();
+    assert_eq!(result, json!(3));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nodes_without_heartbeats_are_dropped() {
+    let namespace = common::namespace();
+    common::start(
+        common::config(&namespace, "silent-node").heartbeat_interval(60u32),
+        vec![math_service()],
+    );
+    let broker = common::start(
+        common::config(&namespace, "caller").heartbeat_timeout(2u32),
+        vec![],
+    );
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    let result = broker.call("math.add", json!({"a": 1, "b": 2})).await;
+    assert!(
+        matches!(result, Err(Error::ServiceNotFound(_))),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn passes_the_transporter_check() {
+    common::check_transporter(Transporter::fake).await;
+}
@@ -0,0 +1,91 @@
+/*
+ * Writes the serializer fixtures with the moleculerjs serializers:
+ *
+ *     cd tests/fixtures && npm install && npm run generate
+ */
+
+"use strict";
+
+const fs = require("fs");
+const path = require("path");
+const { ServiceBroker, Serializers } = require("moleculer");
+
+// the REQ packet of `request.*`
+const request = () => ({
+	ver: "4",
+	sender: "node-js",
+	id: "b6c1a8f0",
+	action: "math.add",
+	params: { a: 1, b: 2 },
+	meta: { user: "john" },
+	timeout: 5000,
+	level: 1,
+	tracing: null,
+	parentID: null,
+	requestID: "b6c1a8f0",
+	caller: null,
+	stream: false
+});
+
+// the RES packet of `response.*`
+const response = () => ({
+	ver: "4",
+	sender: "node-js",
+	id: "b6c1a8f0",
+	success: true,
+	data: Buffer.from([1, 2, 3]),
+	error: null,
+	meta: {},
+	stream: false
+});
+
+// the EVENT packet of `event.notepack`, with the JS values notepack adds to MsgPack
+const event = () => ({
+	ver: "4",
+	sender: "node-js",
+	id: "c3d2e1f0",
+	event: "user.created",
+	data: { at: new Date(1700000000000), tags: ["new", undefined] },
+	meta: {},
+	level: 1,
+	tracing: null,
+	parentID: null,
+	requestID: "c3d2e1f0",
+	caller: null,
+	needAck: null,
+	stream: false,
+	groups: ["users"],
+	broadcast: false
+});
+
+// file extension and name of the moleculerjs serializer
+const formats = [
+	["json", "JSON"],
+	["msgpack", "MsgPack"],
+	["cbor", "CBOR"],
+	// protobufjs with the code moleculerjs generated from its `packets.proto`
+	["protobuf", "ProtoBuf"],
+	// avsc with the per-packet schemas of moleculerjs
+	["avro", "Avro"]
+];
+
+const broker = new ServiceBroker({ logger: false });
+
+for (const [extension, name] of formats) {
+	const serializer = Serializers.resolve(name);
+	serializer.init(broker);
+
+	// serializers may change the packet, each gets its own
+	write(`request.${extension}`, serializer.serialize(request(), "REQ"));
+	write(`response.${extension}`, serializer.serialize(response(), "RES"));
+}
+
+// notepack.io writes the same bytes as msgpack5 for the packets above
+const notepack = Serializers.resolve("Notepack");
+notepack.init(broker);
+write("event.notepack", notepack.serialize(event(), "EVENT"));
+
+function write(file, bytes) {
+	fs.writeFileSync(path.join(__dirname, file), bytes);
+	console.log(`${file}: ${bytes.length} bytes`);
+}
@@ -0,0 +1,15 @@
+//! Needs a Kafka or Redpanda broker, run with:
+//! `KAFKA_BROKERS=localhost:9092 cargo test --features kafka --test kafka_transporter -- --ignored`
+
+#![cfg(feature = "kafka")]
+
+mod common;
+
+use moleculer::config::Transporter;
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a Kafka broker"]
+async fn brokers_talk_over_kafka() {
+    let brokers = std::env::var("KAFKA_BROKERS").unwrap_or_else(|_| "localhost:9092".to_string());
+    common::check_transporter(|| Transporter::kafka(brokers.clone())).await;
+}
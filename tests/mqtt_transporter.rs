@@ -0,0 +1,27 @@
+//! Needs a MQTT broker like mosquitto, run with:
+//! `MQTT_URL=mqtt://localhost:1883 cargo test --features mqtt --test mqtt_transporter -- --ignored`
+
+#![cfg(feature = "mqtt")]
+
+mod common;
+
+use moleculer::config::{MqttOptionsBuilder, Transporter};
+
+fn url() -> String {
+    std::env::var("MQTT_URL").unwrap_or_else(|_| "mqtt://localhost:1883".to_string())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a MQTT broker"]
+async fn brokers_talk_over_mqtt() {
+    common::check_transporter(|| Transporter::mqtt(url())).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a MQTT broker"]
+async fn brokers_talk_over_mqtt_with_qos_1() {
+    common::check_transporter(|| {
+        Transporter::mqtt_with_options(MqttOptionsBuilder::default().url(url()).qos(1u8).build())
+    })
+    .await;
+}
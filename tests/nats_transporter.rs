@@ -0,0 +1,107 @@
+//! Needs a NATS server with JetStream enabled (`nats-server -js`), run with:
+//! `NATS_URL=nats://localhost:4222 cargo test --test nats_transporter -- --ignored`
+
+mod common;
+
+use std::{error::Error as StdError, time::Duration};
+
+use moleculer::{
+    config::{JetStreamOptionsBuilder, NatsOptionsBuilder, Transporter},
+    service::{ActionBuilder, EventBuilder, Service},
+    ActionContext, Error, EventContext,
+};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+fn url() -> String {
+    std::env::var("NATS_URL").unwrap_or_else(|_| "nats://localhost:4222".to_string())
+}
+
+fn jetstream() -> Transporter {
+    let options = NatsOptionsBuilder::default()
+        .url(url())
+        .jetstream(JetStreamOptionsBuilder::default().build())
+        .build();
+
+    Transporter::nats_with_options(options)
+}
+
+/// Service of the `users` group, sending the ids of the `user.created` events it handles
+fn users(tx: mpsc::UnboundedSender<Value>) -> Service {
+    let created = EventBuilder::new("user.created")
+        .add_callback(move |ctx: EventContext| tx.send(ctx.params).map_err(|err| err.to_string()))
+        .build();
+    let ready = ActionBuilder::new("users.ready")
+        .add_callback(|_ctx: ActionContext| Ok::<_, Box<dyn StdError>>(true))
+        .build();
+
+    Service::new("users").add_event(created).add_action(ready)
+}
+
+async fn next_id(rx: &mut mpsc::UnboundedReceiver<Value>) -> i64 {
+    let params = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+        .await
+        .expect("event not received")
+        .unwrap();
+
+    params["id"].as_i64().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a NATS server"]
+async fn brokers_talk_over_nats() {
+    common::check_transporter(|| Transporter::nats(url())).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a NATS server with JetStream"]
+async fn brokers_talk_over_nats_with_jetstream() {
+    common::check_transporter(jetstream).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a NATS server with JetStream"]
+async fn events_wait_for_a_node_of_their_group() {
+    let namespace = common::namespace();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    // on its own runtime, so the node can be stopped
+    let users_runtime = tokio::runtime::Runtime::new().unwrap();
+    {
+        let _guard = users_runtime.enter();
+        common::start(
+            common::config(&namespace, "users-1").transporter(jetstream()),
+            vec![users(tx.clone())],
+        );
+    }
+
+    let broker = common::start(
+        common::config(&namespace, "caller")
+            .transporter(jetstream())
+            .heartbeat_timeout(2u32),
+        vec![],
+    );
+    common::wait_for_action(&broker, "users.ready", json!({})).await;
+
+    broker.emit("user.created", json!({"id": 1})).await.unwrap();
+    assert_eq!(next_id(&mut rx).await, 1);
+
+    users_runtime.shutdown_background();
+
+    // until the caller notices the node is gone
+    for _ in 0..100 {
+        match broker.clone().call("users.ready", json!({})).await {
+            Err(Error::ServiceNotFound(_)) => break,
+            _ => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+
+    // stored until a node of the group reads it
+    broker.emit("user.created", json!({"id": 2})).await.unwrap();
+
+    common::start(
+        common::config(&namespace, "users-2").transporter(jetstream()),
+        vec![users(tx)],
+    );
+    assert_eq!(next_id(&mut rx).await, 2);
+}
@@ -0,0 +1,15 @@
+//! Needs a Redis server, run with:
+//! `REDIS_URL=redis://localhost:6379 cargo test --features redis --test redis_transporter -- --ignored`
+
+#![cfg(feature = "redis")]
+
+mod common;
+
+use moleculer::config::Transporter;
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a Redis server"]
+async fn brokers_talk_over_redis() {
+    let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string());
+    common::check_transporter(|| Transporter::redis(url.clone())).await;
+}
@@ -0,0 +1,686 @@
+//! Packets going through each serializer, and packets sent by moleculerjs nodes.
+//!
+//! The fixtures are the packets of a moleculerjs node, see `tests/fixtures/README.md`.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use moleculer::config::{PacketKind, PacketSerializer, PacketSerializerError, Serializer};
+use serde_json::{json, Value};
+
+/// A packet of each kind, with every field moleculerjs sends set
+fn packets() -> Vec<(PacketKind, Value)> {
+    vec![
+        (
+            PacketKind::Event,
+            json!({
+                "ver": "4",
+                "sender": "node-1",
+                "id": "event-1",
+                "event": "user.created",
+                "data": {"id": 1, "name": "John"},
+                "groups": ["users", "mail"],
+                "broadcast": true,
+                "meta": {"tenant": "acme"},
+                "level": 2,
+                "tracing": true,
+                "parentID": "parent-1",
+                "requestID": "request-1",
+                "stream": true,
+                "seq": 3,
+                "caller": "users",
+                "needAck": true,
+            }),
+        ),
+        (
+            PacketKind::Request,
+            json!({
+                "ver": "4",
+                "sender": "node-1",
+                "id": "request-2",
+                "action": "math.add",
+                "params": {"a": 1, "b": 2.5},
+                "meta": {"tenant": "acme"},
+                "timeout": 5000.0,
+                "level": 1,
+                "tracing": true,
+                "parentID": "parent-1",
+                "requestID": "request-1",
+                "stream": true,
+                "seq": 1,
+                "caller": "api",
+            }),
+        ),
+        (
+            PacketKind::Response,
+            json!({
+                "ver": "4",
+                "sender": "node-2",
+                "id": "request-2",
+                "success": false,
+                "data": null,
+                "error": {
+                    "name": "MoleculerError",
+                    "message": "Something happened",
+                    "code": 500,
+                    "type": "FAILED",
+                    "data": {"a": 1},
+                },
+                "meta": {"tenant": "acme"},
+                "stream": true,
+                "seq": 2,
+            }),
+        ),
+        (
+            PacketKind::Discover,
+            json!({"ver": "4", "sender": "node-1"}),
+        ),
+        (
+            PacketKind::Info,
+            json!({
+                "ver": "4",
+                "sender": "node-1",
+                "services": [{
+                    "name": "math",
+                    "settings": {},
+                    "metadata": {},
+                    "actions": {"math.add": {"name": "math.add"}},
+                    "events": {},
+                }],
+                "config": {"namespace": ""},
+                "ipList": ["10.0.0.1", "10.0.0.2"],
+                "hostname": "host-1",
+                "client": {"type": "rust", "version": "0.4.0", "langVersion": "1.80.0"},
+                "seq": 4,
+                "instanceID": "instance-1",
+                "metadata": {"region": "eu"},
+            }),
+        ),
+        (
+            PacketKind::Disconnect,
+            json!({"ver": "4", "sender": "node-1"}),
+        ),
+        (
+            PacketKind::Heartbeat,
+            json!({"ver": "4", "sender": "node-1", "cpu": 12.5}),
+        ),
+        (
+            PacketKind::Ping,
+            json!({"ver": "4", "sender": "node-1", "time": 1_700_000_000_000_i64, "id": "ping-1"}),
+        ),
+        (
+            PacketKind::Pong,
+            json!({
+                "ver": "4",
+                "sender": "node-2",
+                "time": 1_700_000_000_000_i64,
+                "arrived": 1_700_000_000_005_i64,
+                "id": "ping-1",
+            }),
+        ),
+        (
+            PacketKind::GossipHello,
+            json!({"ver": "4", "sender": "node-1", "host": "10.0.0.1", "port": 4000}),
+        ),
+        (
+            PacketKind::GossipRequest,
+            json!({
+                "ver": "4",
+                "sender": "node-1",
+                "online": {"node-1": [4, 2, 12]},
+                "offline": {"node-3": 7},
+            }),
+        ),
+        (
+            PacketKind::GossipResponse,
+            json!({
+                "ver": "4",
+                "sender": "node-2",
+                "online": {"node-2": [{"sender": "node-2"}, 1, 5]},
+                "offline": {"node-4": 3},
+            }),
+        ),
+    ]
+}
+
+fn round_trip(serializer: Serializer) {
+    for (kind, packet) in packets() {
+        let bytes = serializer.serialize(kind, packet.clone()).unwrap();
+        let received = serializer.deserialize(kind, &bytes).unwrap();
+
+        assert_eq!(received, packet, "{:?} packet changed", kind);
+    }
+}
+
+/// The REQ packet of `fixtures/request.*`
+fn request() -> Value {
+    json!({
+        "ver": "4",
+        "sender": "node-js",
+        "id": "b6c1a8f0",
+        "action": "math.add",
+        "params": {"a": 1, "b": 2},
+        "meta": {"user": "john"},
+        "timeout": 5000,
+        "level": 1,
+        "tracing": null,
+        "parentID": null,
+        "requestID": "b6c1a8f0",
+        "caller": null,
+        "stream": false,
+    })
+}
+
+/// The RES packet of `fixtures/response.*`, a `Buffer` of 3 bytes
+fn response() -> Value {
+    json!({
+        "ver": "4",
+        "sender": "node-js",
+        "id": "b6c1a8f0",
+        "success": true,
+        "data": {"type": "Buffer", "data": [1, 2, 3]},
+        "error": null,
+        "meta": {},
+        "stream": false,
+    })
+}
+
+#[test]
+fn json_round_trip() {
+    round_trip(Serializer::Json);
+}
+
+#[test]
+fn json_reads_node_packets() {
+    let serializer = Serializer::Json;
+
+    let received = serializer
+        .deserialize(PacketKind::Request, include_bytes!("fixtures/request.json"))
+        .unwrap();
+    assert_eq!(received, request());
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Response,
+            include_bytes!("fixtures/response.json"),
+        )
+        .unwrap();
+    assert_eq!(received, response());
+}
+
+#[test]
+fn msgpack_round_trip() {
+    round_trip(Serializer::MsgPack);
+}
+
+#[test]
+fn msgpack_reads_node_packets() {
+    let serializer = Serializer::MsgPack;
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Request,
+            include_bytes!("fixtures/request.msgpack"),
+        )
+        .unwrap();
+    assert_eq!(received, request());
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Response,
+            include_bytes!("fixtures/response.msgpack"),
+        )
+        .unwrap();
+    assert_eq!(received, response());
+}
+
+#[test]
+fn msgpack_writes_buffers_as_bin() {
+    let bytes = Serializer::MsgPack
+        .serialize(PacketKind::Response, response())
+        .unwrap();
+
+    // `data` followed by a bin 8 of 3 bytes
+    let data = [&[0xa4][..], b"data", &[0xc4, 3, 1, 2, 3]].concat();
+    assert!(bytes.windows(data.len()).any(|window| window == data));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn brokers_talk_with_msgpack() {
+    common::check_brokers(|config| config.serializer(Serializer::MsgPack)).await;
+}
+
+#[test]
+fn cbor_round_trip() {
+    round_trip(Serializer::Cbor);
+}
+
+#[test]
+fn cbor_reads_node_packets() {
+    let serializer = Serializer::Cbor;
+
+    let received = serializer
+        .deserialize(PacketKind::Request, include_bytes!("fixtures/request.cbor"))
+        .unwrap();
+    assert_eq!(received, request());
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Response,
+            include_bytes!("fixtures/response.cbor"),
+        )
+        .unwrap();
+    assert_eq!(received, response());
+}
+
+#[test]
+fn cbor_writes_buffers_as_byte_strings() {
+    let mut packet = response();
+    packet["data"] = json!({"file": {"type": "Buffer", "data": [1, 2, 3]}, "size": 3});
+
+    let bytes = Serializer::Cbor
+        .serialize(PacketKind::Response, packet.clone())
+        .unwrap();
+
+    // `file` followed by a byte string of 3 bytes
+    let file = [&[0x64][..], b"file", &[0x43, 1, 2, 3]].concat();
+    assert!(bytes.windows(file.len()).any(|window| window == file));
+
+    let received = Serializer::Cbor
+        .deserialize(PacketKind::Response, &bytes)
+        .unwrap();
+    assert_eq!(received, packet);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn brokers_talk_with_cbor() {
+    common::check_brokers(|config| config.serializer(Serializer::Cbor)).await;
+}
+
+/// Field of a message of the moleculerjs `packets.proto`, to write the expected bytes by hand
+enum Field {
+    Str(u32, &'static str),
+    Bytes(u32, Vec<u8>),
+    Varint(u32, u64),
+    Double(u32, f64),
+    Message(u32, Vec<Field>),
+}
+
+fn varint(mut value: u64, bytes: &mut Vec<u8>) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn proto(fields: Vec<Field>) -> Vec<u8> {
+    let mut bytes = vec![];
+
+    for field in fields {
+        let (tag, wire_type, value) = match field {
+            Field::Str(tag, value) => (tag, 2, value.as_bytes().to_vec()),
+            Field::Bytes(tag, value) => (tag, 2, value),
+            Field::Message(tag, fields) => (tag, 2, proto(fields)),
+            Field::Varint(tag, value) => {
+                let mut encoded = vec![];
+                varint(value, &mut encoded);
+                (tag, 0, encoded)
+            }
+            Field::Double(tag, value) => (tag, 1, value.to_le_bytes().to_vec()),
+        };
+
+        varint(u64::from(tag << 3 | wire_type), &mut bytes);
+        if wire_type == 2 {
+            varint(value.len() as u64, &mut bytes);
+        }
+        bytes.extend(value);
+    }
+
+    bytes
+}
+
+/// Field numbers of `packets.proto`, in the order of [packets()]
+fn proto_packets() -> Vec<Vec<Field>> {
+    use Field::*;
+
+    // `DataType`: 1 is NULL, 2 is JSON
+    vec![
+        vec![
+            Str(1, "4"),
+            Str(2, "node-1"),
+            Str(3, "event-1"),
+            Str(4, "user.created"),
+            Bytes(5, br#"{"id":1,"name":"John"}"#.to_vec()),
+            Varint(6, 2),
+            Str(7, "users"),
+            Str(7, "mail"),
+            Varint(8, 1),
+            Str(9, r#"{"tenant":"acme"}"#),
+            Varint(10, 2),
+            Varint(11, 1),
+            Str(12, "parent-1"),
+            Str(13, "request-1"),
+            Varint(14, 1),
+            Varint(15, 3),
+            Str(16, "users"),
+            Varint(17, 1),
+        ],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-1"),
+            Str(3, "request-2"),
+            Str(4, "math.add"),
+            Bytes(5, br#"{"a":1,"b":2.5}"#.to_vec()),
+            Varint(6, 2),
+            Str(7, r#"{"tenant":"acme"}"#),
+            Double(8, 5000.0),
+            Varint(9, 1),
+            Varint(10, 1),
+            Str(11, "parent-1"),
+            Str(12, "request-1"),
+            Varint(13, 1),
+            Varint(14, 1),
+            Str(15, "api"),
+        ],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-2"),
+            Str(3, "request-2"),
+            Varint(6, 1),
+            Str(
+                7,
+                r#"{"code":500,"data":{"a":1},"message":"Something happened","name":"MoleculerError","type":"FAILED"}"#,
+            ),
+            Str(8, r#"{"tenant":"acme"}"#),
+            Varint(9, 1),
+            Varint(10, 2),
+        ],
+        vec![Str(1, "4"), Str(2, "node-1")],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-1"),
+            Str(
+                3,
+                r#"[{"actions":{"math.add":{"name":"math.add"}},"events":{},"metadata":{},"name":"math","settings":{}}]"#,
+            ),
+            Str(4, r#"{"namespace":""}"#),
+            Str(5, "10.0.0.1"),
+            Str(5, "10.0.0.2"),
+            Str(6, "host-1"),
+            Message(7, vec![Str(1, "rust"), Str(2, "0.4.0"), Str(3, "1.80.0")]),
+            Varint(8, 4),
+            Str(9, "instance-1"),
+            Str(10, r#"{"region":"eu"}"#),
+        ],
+        vec![Str(1, "4"), Str(2, "node-1")],
+        vec![Str(1, "4"), Str(2, "node-1"), Double(3, 12.5)],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-1"),
+            Varint(3, 1_700_000_000_000),
+            Str(4, "ping-1"),
+        ],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-2"),
+            Varint(3, 1_700_000_000_000),
+            Varint(4, 1_700_000_000_005),
+            Str(5, "ping-1"),
+        ],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-1"),
+            Str(3, "10.0.0.1"),
+            Varint(4, 4000),
+        ],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-1"),
+            Str(3, r#"{"node-1":[4,2,12]}"#),
+            Str(4, r#"{"node-3":7}"#),
+        ],
+        vec![
+            Str(1, "4"),
+            Str(2, "node-2"),
+            Str(3, r#"{"node-2":[{"sender":"node-2"},1,5]}"#),
+            Str(4, r#"{"node-4":3}"#),
+        ],
+    ]
+}
+
+#[test]
+fn protobuf_round_trip() {
+    round_trip(Serializer::ProtoBuf);
+}
+
+#[test]
+fn protobuf_uses_the_moleculerjs_field_numbers() {
+    for ((kind, packet), fields) in packets().into_iter().zip(proto_packets()) {
+        let expected = proto(fields);
+
+        let bytes = Serializer::ProtoBuf
+            .serialize(kind, packet.clone())
+            .unwrap();
+        assert_eq!(bytes, expected, "{:?} packet", kind);
+
+        let received = Serializer::ProtoBuf.deserialize(kind, &expected).unwrap();
+        assert_eq!(received, packet, "{:?} packet", kind);
+    }
+}
+
+#[test]
+fn protobuf_reads_node_packets() {
+    let serializer = Serializer::ProtoBuf;
+
+    // proto3 has no null, unset fields are read with their default value
+    let mut expected = request();
+    expected
+        .as_object_mut()
+        .unwrap()
+        .retain(|_, value| !value.is_null());
+    expected["tracing"] = json!(false);
+    expected["seq"] = json!(0);
+    // a double
+    expected["timeout"] = json!(5000.0);
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Request,
+            include_bytes!("fixtures/request.protobuf"),
+        )
+        .unwrap();
+    assert_eq!(received, expected);
+
+    let mut expected = response();
+    expected.as_object_mut().unwrap().remove("error");
+    expected["seq"] = json!(0);
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Response,
+            include_bytes!("fixtures/response.protobuf"),
+        )
+        .unwrap();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn protobuf_sends_buffers_as_bytes() {
+    let bytes = Serializer::ProtoBuf
+        .serialize(PacketKind::Response, response())
+        .unwrap();
+
+    // `data` holds the bytes and `dataType` is BUFFER
+    let data = proto(vec![Field::Bytes(5, vec![1, 2, 3]), Field::Varint(6, 3)]);
+    assert!(bytes.windows(data.len()).any(|window| window == data));
+
+    let received = Serializer::ProtoBuf
+        .deserialize(PacketKind::Response, &bytes)
+        .unwrap();
+    assert_eq!(received["data"], response()["data"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn brokers_talk_with_protobuf() {
+    common::check_brokers(|config| config.serializer(Serializer::ProtoBuf)).await;
+}
+
+#[test]
+fn notepack_round_trip() {
+    round_trip(Serializer::Notepack);
+}
+
+#[test]
+fn notepack_reads_node_packets() {
+    let serializer = Serializer::Notepack;
+
+    // notepack.io writes the same bytes as msgpack5 for these packets
+    let received = serializer
+        .deserialize(
+            PacketKind::Request,
+            include_bytes!("fixtures/request.msgpack"),
+        )
+        .unwrap();
+    assert_eq!(received, request());
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Response,
+            include_bytes!("fixtures/response.msgpack"),
+        )
+        .unwrap();
+    assert_eq!(received, response());
+}
+
+#[test]
+fn notepack_reads_js_values() {
+    let received = Serializer::Notepack
+        .deserialize(PacketKind::Event, include_bytes!("fixtures/event.notepack"))
+        .unwrap();
+
+    // a `Date` is its time in milliseconds, `undefined` is null
+    assert_eq!(
+        received["data"],
+        json!({"at": 1_700_000_000_000.0, "tags": ["new", null]})
+    );
+    assert_eq!(received["event"], json!("user.created"));
+    assert_eq!(received["groups"], json!(["users"]));
+}
+
+#[test]
+fn notepack_writes_buffers_as_bin() {
+    let bytes = Serializer::Notepack
+        .serialize(PacketKind::Response, response())
+        .unwrap();
+
+    // `data` followed by a bin 8 of 3 bytes
+    let data = [&[0xa4][..], b"data", &[0xc4, 3, 1, 2, 3]].concat();
+    assert!(bytes.windows(data.len()).any(|window| window == data));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn brokers_talk_with_notepack() {
+    common::check_brokers(|config| config.serializer(Serializer::Notepack)).await;
+}
+
+#[test]
+fn avro_round_trip() {
+    round_trip(Serializer::Avro);
+}
+
+#[test]
+fn avro_reads_node_packets() {
+    let serializer = Serializer::Avro;
+
+    // missing nullable fields are read as null, `timeout` is a double
+    let mut expected = request();
+    expected["seq"] = Value::Null;
+    expected["timeout"] = json!(5000.0);
+
+    let received = serializer
+        .deserialize(PacketKind::Request, include_bytes!("fixtures/request.avro"))
+        .unwrap();
+    assert_eq!(received, expected);
+
+    let mut expected = response();
+    expected["seq"] = Value::Null;
+
+    let received = serializer
+        .deserialize(
+            PacketKind::Response,
+            include_bytes!("fixtures/response.avro"),
+        )
+        .unwrap();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn avro_writes_the_moleculerjs_schemas() {
+    let serializer = Serializer::Avro;
+
+    let bytes = serializer
+        .serialize(PacketKind::Request, request())
+        .unwrap();
+    assert_eq!(bytes, include_bytes!("fixtures/request.avro"));
+
+    let bytes = serializer
+        .serialize(PacketKind::Response, response())
+        .unwrap();
+    assert_eq!(bytes, include_bytes!("fixtures/response.avro"));
+}
+
+#[test]
+fn avro_sends_buffers_as_bytes() {
+    let bytes = Serializer::Avro
+        .serialize(PacketKind::Response, response())
+        .unwrap();
+
+    // `data` is the bytes branch of its union, with 3 bytes, and `dataType` is BUFFER
+    let data = [0x02, 0x06, 1, 2, 3, 0x06];
+    assert!(bytes.windows(data.len()).any(|window| window == data));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn brokers_talk_with_avro() {
+    common::check_brokers(|config| config.serializer(Serializer::Avro)).await;
+}
+
+/// Wraps the JSON serializer, recording the kind of each packet going through it
+#[derive(Debug, Clone, Default)]
+struct Recording {
+    serialized: Arc<Mutex<Vec<PacketKind>>>,
+    deserialized: Arc<Mutex<Vec<PacketKind>>>,
+}
+
+impl PacketSerializer for Recording {
+    fn serialize(&self, kind: PacketKind, packet: Value) -> Result<Vec<u8>, PacketSerializerError> {
+        self.serialized.lock().unwrap().push(kind);
+        Serializer::Json.serialize(kind, packet)
+    }
+
+    fn deserialize(&self, kind: PacketKind, bytes: &[u8]) -> Result<Value, PacketSerializerError> {
+        self.deserialized.lock().unwrap().push(kind);
+        Serializer::Json.deserialize(kind, bytes)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn brokers_talk_with_custom() {
+    let recording = Recording::default();
+    common::check_brokers(|config| config.serializer(Serializer::custom(recording.clone()))).await;
+
+    let expected = [
+        PacketKind::Info,
+        PacketKind::Request,
+        PacketKind::Response,
+        PacketKind::Event,
+    ];
+    for kinds in &[&recording.serialized, &recording.deserialized] {
+        let kinds = kinds.lock().unwrap();
+        for kind in &expected {
+            assert!(kinds.contains(kind), "no {:?} packet in {:?}", kind, kinds);
+        }
+    }
+}
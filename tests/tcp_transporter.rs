@@ -0,0 +1,115 @@
+//! Brokers talking to each other with the TCP transporter on the loopback interface.
+
+mod common;
+
+use std::{error::Error as StdError, net::TcpListener, time::Duration};
+
+use moleculer::{
+    config::{ConfigBuilder, TcpOptionsBuilder, Transporter},
+    service::{ActionBuilder, EventBuilder, Service},
+    ActionContext, EventContext,
+};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Node listening on the port, finding the other nodes through the `urls` option instead of UDP
+fn config(namespace: &str, node_id: &str, port: u16, peers: &[(&str, u16)]) -> ConfigBuilder {
+    let urls: Vec<String> = peers
+        .iter()
+        .map(|(node_id, port)| format!("127.0.0.1:{}/{}", port, node_id))
+        .collect();
+
+    let options = TcpOptionsBuilder::default()
+        .port(port)
+        .urls(urls)
+        .udp_discovery(false)
+        .gossip_period(Duration::from_millis(100))
+        .build();
+
+    ConfigBuilder::default()
+        .namespace(namespace)
+        .node_id(node_id)
+        .transporter(Transporter::tcp(options))
+        .heartbeat_interval(1u32)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn brokers_talk_over_loopback() {
+    let namespace = common::namespace();
+    let (math_port, listener_port, caller_port) = (free_port(), free_port(), free_port());
+
+    let add = ActionBuilder::new("math.add")
+        .add_callback(|ctx: ActionContext| {
+            let sum = ctx.params["a"].as_i64().unwrap_or_default()
+                + ctx.params["b"].as_i64().unwrap_or_default();
+            Ok::<_, Box<dyn StdError>>(sum)
+        })
+        .build();
+    common::start(
+        config(
+            &namespace,
+            "math-node",
+            math_port,
+            &[("caller", caller_port)],
+        ),
+        vec![Service::new("math").add_action(add)],
+    );
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let created = EventBuilder::new("user.created")
+        .add_callback(move |ctx: EventContext| tx.send(ctx.params).map_err(|err| err.to_string()))
+        .build();
+    let ready = ActionBuilder::new("listener.ready")
+        .add_callback(|_ctx: ActionContext| Ok::<_, Box<dyn StdError>>(true))
+        .build();
+    common::start(
+        config(
+            &namespace,
+            "listener-node",
+            listener_port,
+            &[("caller", caller_port)],
+        ),
+        vec![Service::new("listener")
+            .add_event(created)
+            .add_action(ready)],
+    );
+
+    // only knows the other nodes through gossip
+    let broker = common::start(config(&namespace, "caller", caller_port, &[]), vec![]);
+
+    common::wait_for_action(&broker, "math.add", json!({"a": 0, "b": 0})).await;
+    common::wait_for_action(&broker, "listener.ready", json!({})).await;
+
+    let result = broker
+        .clone()
+        .call("math.add", json!({"a": 1, "b": 2}))
+        .await
+        .unwrap();
+    assert_eq!(result, json!(3));
+
+    broker.emit("user.created", json!({"id": 1})).await.unwrap();
+    broker
+        .broadcast("user.created", json!({"id": 2}))
+        .await
+        .unwrap();
+
+    // callbacks run on their own task, in any order
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let params = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        ids.push(params["id"].as_i64().unwrap());
+    }
+    ids.sort_unstable();
+    assert_eq!(ids, vec![1, 2]);
+}